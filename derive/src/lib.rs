@@ -0,0 +1,347 @@
+//! `#[derive(ElasticMapping)]` for `uiuifree-elastic`. See
+//! `uiuifree_elastic::ElasticMapping` for the attributes.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::meta::ParseNestedMeta;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitBool, LitInt, LitStr};
+
+#[proc_macro_derive(ElasticMapping, attributes(elastic))]
+pub fn derive_elastic_mapping(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(v) => v.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// A mapping parameter value taken from an attribute.
+enum Param {
+    Str(String),
+    Bool(bool),
+    Int(i64),
+}
+
+impl Param {
+    fn parse(meta: &ParseNestedMeta) -> syn::Result<Param> {
+        let lookahead = meta.value()?.lookahead1();
+        let input = meta.input;
+        if lookahead.peek(LitStr) {
+            Ok(Param::Str(input.parse::<LitStr>()?.value()))
+        } else if lookahead.peek(LitBool) {
+            Ok(Param::Bool(input.parse::<LitBool>()?.value))
+        } else if lookahead.peek(LitInt) {
+            Ok(Param::Int(input.parse::<LitInt>()?.base10_parse()?))
+        } else {
+            Err(lookahead.error())
+        }
+    }
+
+    fn string(meta: &ParseNestedMeta) -> syn::Result<String> {
+        match Param::parse(meta)? {
+            Param::Str(v) => Ok(v),
+            _ => Err(meta.error("expected a string")),
+        }
+    }
+
+    /// The serialized side of a serde `name = "..."` or
+    /// `name(serialize = "...", deserialize = "...")` attribute.
+    fn serialize_string(meta: &ParseNestedMeta) -> syn::Result<Option<String>> {
+        if meta.input.peek(syn::Token![=]) {
+            return Param::string(meta).map(Some);
+        }
+        let mut value = None;
+        meta.parse_nested_meta(|side| {
+            if side.path.is_ident("serialize") {
+                value = Some(Param::string(&side)?);
+            } else {
+                side.value()?.parse::<syn::Expr>()?;
+            }
+            Ok(())
+        })?;
+        Ok(value)
+    }
+
+    fn tokens(&self) -> TokenStream2 {
+        let value = quote!(::uiuifree_elastic::mapping::__private::Value);
+        match self {
+            Param::Str(v) => quote!(#value::from(#v)),
+            Param::Bool(v) => quote!(#value::from(#v)),
+            Param::Int(v) => quote!(#value::from(#v)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct FieldAttrs {
+    name: Option<String>,
+    skip: bool,
+    flatten: bool,
+    params: Vec<(String, Param)>,
+    sub_fields: Vec<(String, Vec<(String, Param)>)>,
+}
+
+impl FieldAttrs {
+    fn parse(field: &syn::Field) -> syn::Result<FieldAttrs> {
+        let mut attrs = FieldAttrs::default();
+        for attr in &field.attrs {
+            if attr.path().is_ident("serde") {
+                // Only what changes the stored field; the rest is serde's business.
+                let _ = attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("rename") {
+                        if let Some(name) = Param::serialize_string(&meta)? {
+                            attrs.name.get_or_insert(name);
+                        }
+                    } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_serializing") {
+                        attrs.skip = true;
+                    } else if meta.path.is_ident("flatten") {
+                        attrs.flatten = true;
+                    } else if meta.input.peek(syn::Token![=]) {
+                        meta.value()?.parse::<syn::Expr>()?;
+                    } else if meta.input.peek(syn::token::Paren) {
+                        meta.parse_nested_meta(|_| Ok(()))?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+        for attr in &field.attrs {
+            if !attr.path().is_ident("elastic") {
+                continue;
+            }
+            attr.parse_nested_meta(|meta| {
+                let key = meta
+                    .path
+                    .get_ident()
+                    .map(|v| v.to_string())
+                    .ok_or_else(|| meta.error("expected a name"))?;
+                match key.as_str() {
+                    "skip" => attrs.skip = true,
+                    "rename" => attrs.name = Some(Param::string(&meta)?),
+                    "keyword" | "text" | "date" | "nested" | "object" => {
+                        attrs.params.push(("type".to_string(), Param::Str(key)));
+                    }
+                    "fields" => meta.parse_nested_meta(|sub| {
+                        let name = sub
+                            .path
+                            .get_ident()
+                            .map(|v| v.to_string())
+                            .ok_or_else(|| sub.error("expected a multi-field name"))?;
+                        let mut params = vec![];
+                        if sub.input.peek(syn::token::Paren) {
+                            sub.parse_nested_meta(|param| {
+                                let key = param
+                                    .path
+                                    .get_ident()
+                                    .map(|v| v.to_string())
+                                    .ok_or_else(|| param.error("expected a name"))?;
+                                params.push((key, Param::parse(&param)?));
+                                Ok(())
+                            })?;
+                        } else {
+                            params.push(("type".to_string(), Param::Str(Param::string(&sub)?)));
+                        }
+                        attrs.sub_fields.push((name, params));
+                        Ok(())
+                    })?,
+                    _ => attrs.params.push((key, Param::parse(&meta)?)),
+                }
+                Ok(())
+            })?;
+        }
+        Ok(attrs)
+    }
+}
+
+/// A field name under serde's `rename_all` rule, `None` for an unknown rule.
+fn rename_field(rule: &str, field: &str) -> Option<String> {
+    let pascal = || {
+        let mut pascal = String::new();
+        let mut capitalize = true;
+        for ch in field.chars() {
+            if ch == '_' {
+                capitalize = true;
+            } else if capitalize {
+                pascal.push(ch.to_ascii_uppercase());
+                capitalize = false;
+            } else {
+                pascal.push(ch);
+            }
+        }
+        pascal
+    };
+    Some(match rule {
+        "lowercase" | "snake_case" => field.to_string(),
+        "UPPERCASE" | "SCREAMING_SNAKE_CASE" => field.to_ascii_uppercase(),
+        "PascalCase" => pascal(),
+        "camelCase" => {
+            let pascal = pascal();
+            let mut chars = pascal.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+                None => pascal,
+            }
+        }
+        "kebab-case" => field.replace('_', "-"),
+        "SCREAMING-KEBAB-CASE" => field.to_ascii_uppercase().replace('_', "-"),
+        _ => return None,
+    })
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let fields = match &input.data {
+        Data::Struct(v) => match &v.fields {
+            Fields::Named(v) => &v.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    "ElasticMapping needs named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "ElasticMapping can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut dynamic = None;
+    let mut rename_all = None;
+    for attr in &input.attrs {
+        if attr.path().is_ident("serde") {
+            let mut rule = None;
+            let _ = attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename_all") {
+                    rule = Param::serialize_string(&meta)?;
+                } else if meta.input.peek(syn::Token![=]) {
+                    meta.value()?.parse::<syn::Expr>()?;
+                } else if meta.input.peek(syn::token::Paren) {
+                    meta.parse_nested_meta(|_| Ok(()))?;
+                }
+                Ok(())
+            });
+            if let Some(rule) = rule {
+                if rename_field(&rule, "").is_none() {
+                    return Err(syn::Error::new_spanned(
+                        attr,
+                        format!("unknown serde rename_all rule `{}`", rule),
+                    ));
+                }
+                rename_all = Some(rule);
+            }
+        }
+        if attr.path().is_ident("elastic") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("dynamic") {
+                    dynamic = Some(match Param::parse(&meta)? {
+                        Param::Str(v) => v,
+                        Param::Bool(v) => v.to_string(),
+                        Param::Int(_) => return Err(meta.error("expected true, false or a string")),
+                    });
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown elastic attribute"))
+                }
+            })?;
+        }
+    }
+
+    let private = quote!(::uiuifree_elastic::mapping::__private);
+    let mut entries = vec![];
+    let mut flattened = vec![];
+    for field in fields {
+        let attrs = FieldAttrs::parse(field)?;
+        if attrs.skip {
+            continue;
+        }
+        let ty = &field.ty;
+        if attrs.flatten {
+            // serde writes the inner fields next to the outer ones.
+            flattened.push(quote! {
+                #private::flatten(
+                    &mut properties,
+                    <#ty as ::uiuifree_elastic::mapping::FieldMapping>::field_mapping(),
+                );
+            });
+            continue;
+        }
+        let name = match &attrs.name {
+            Some(v) => v.clone(),
+            None => {
+                let name = field
+                    .ident
+                    .as_ref()
+                    .map(|v| v.to_string().trim_start_matches("r#").to_string())
+                    .unwrap_or_default();
+                match &rename_all {
+                    Some(rule) => rename_field(rule, &name).unwrap_or(name),
+                    None => name,
+                }
+            }
+        };
+        let sets = attrs.params.iter().map(|(key, param)| {
+            let value = param.tokens();
+            quote!(#private::set(&mut field, #key, #value);)
+        });
+        let sub_fields = attrs.sub_fields.iter().map(|(name, params)| {
+            let sets = params.iter().map(|(key, param)| {
+                let value = param.tokens();
+                quote!(#private::set(&mut sub, #key, #value);)
+            });
+            quote! {
+                let mut sub = #private::Value::Null;
+                #(#sets)*
+                #private::set_sub_field(&mut field, #name, sub);
+            }
+        });
+        // An explicit scalar type does not need the Rust type's mapping, so
+        // types without a `FieldMapping` impl (dates from other crates) work.
+        let scalar = attrs.params.iter().any(|(key, param)| {
+            key == "type" && !matches!(param, Param::Str(v) if v == "object" || v == "nested")
+        });
+        let base = match scalar {
+            true => quote!(#private::Value::Null),
+            false => quote!(<#ty as ::uiuifree_elastic::mapping::FieldMapping>::field_mapping()),
+        };
+        entries.push(quote! {
+            (#name, {
+                let mut field = #base;
+                #(#sets)*
+                #(#sub_fields)*
+                field
+            })
+        });
+    }
+
+    let dynamic = match dynamic {
+        Some(v) => quote! {
+            fn dynamic() -> ::std::option::Option<&'static str> {
+                ::std::option::Option::Some(#v)
+            }
+        },
+        None => quote!(),
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::uiuifree_elastic::mapping::ElasticMapping for #ident #ty_generics #where_clause {
+            fn properties() -> #private::Value {
+                #[allow(unused_mut)]
+                let mut properties = #private::properties(::std::vec![#(#entries),*]);
+                #(#flattened)*
+                properties
+            }
+            #dynamic
+        }
+
+        impl #impl_generics ::uiuifree_elastic::mapping::FieldMapping for #ident #ty_generics #where_clause {
+            fn field_mapping() -> #private::Value {
+                #private::object::<Self>()
+            }
+        }
+    })
+}
@@ -0,0 +1,922 @@
+use crate::error::ElasticError;
+use crate::retry::RetryPolicy;
+use crate::{
+    bool_to_refresh, bulk_is_idempotent, ndjson_body, parse_response, BulkApi, ElasticApi,
+};
+use elasticsearch::BulkParts;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio::task::JoinHandle;
+
+impl BulkApi {
+    /// Sends `operations` in one bulk request and returns the bulk response.
+    /// No operations send nothing and give an empty response.
+    pub async fn operations(
+        &self,
+        operations: &[BulkOperation],
+        refresh: bool,
+    ) -> Result<BulkResponse, ElasticError> {
+        if operations.is_empty() {
+            return Ok(BulkResponse::default());
+        }
+        let lines = operations.iter().flat_map(|v| v.lines()).collect();
+        self.bulk::<Value>(lines, refresh).await
+    }
+
+    /// Sends `operations`, then resends only the items rejected with 429 or
+    /// 503 with backoff, so documents with generated ids are not written
+    /// twice. Uses the attempts and backoff of the `with_retry` policy, or
+    /// [`RetryPolicy::default`]; its statuses narrow the two above.
+    ///
+    /// A whole request rejected with 429 or 503 is sent again. After a 502,
+    /// a 504 or a lost connection some items may already be written, so it
+    /// is sent again only when every action is safe to repeat: index and
+    /// create with an explicit `_id`, and no update.
+    pub async fn operations_with_retry(
+        &self,
+        operations: &[BulkOperation],
+        refresh: bool,
+    ) -> Result<BulkReport, ElasticError> {
+        if operations.is_empty() {
+            return Ok(BulkReport::default());
+        }
+        let policy = self.retry.clone().unwrap_or_default();
+        let operations = operations.iter().map(|v| (v.action(), v.body())).collect();
+        let report = send_retrying(&self.api, &policy, refresh, operations).await;
+        if self.fail_on_item_errors && !report.failed.is_empty() {
+            return Err(ElasticError::BulkPartialFailure(report.failed));
+        }
+        Ok(report)
+    }
+
+    /// Long-lived bulk writer, see [`BulkIngester`].
+    pub fn ingester(&self) -> BulkIngesterBuilder {
+        BulkIngesterBuilder::new(&self.api)
+    }
+}
+
+/// One action of a bulk request, serialized by [`lines`](BulkOperation::lines)
+/// into its action line and, except for `delete`, its body line.
+///
+/// ```
+/// use serde_json::json;
+/// use uiuifree_elastic::BulkOperation;
+///
+/// let op = BulkOperation::index("users", &json!({"name": "alice"}))
+///     .id("1")
+///     .routing("a")
+///     .pipeline("enrich");
+/// assert_eq!(
+///     op.lines(),
+///     [
+///         json!({"index": {"_index": "users", "_id": "1", "routing": "a", "pipeline": "enrich"}}),
+///         json!({"name": "alice"}),
+///     ]
+/// );
+/// assert_eq!(
+///     BulkOperation::delete("users", "2").lines(),
+///     [json!({"delete": {"_index": "users", "_id": "2"}})]
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum BulkOperation {
+    /// Creates or replaces the document.
+    Index {
+        meta: BulkMeta,
+        source: Value,
+    },
+    /// Fails if the document exists.
+    Create {
+        meta: BulkMeta,
+        source: Value,
+    },
+    Update {
+        meta: BulkMeta,
+        update: BulkUpdate,
+    },
+    Delete {
+        meta: BulkMeta,
+    },
+}
+
+/// Action line fields of a [`BulkOperation`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BulkMeta {
+    #[serde(rename = "_index", skip_serializing_if = "Option::is_none")]
+    pub index: Option<String>,
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub routing: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub if_seq_no: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub if_primary_term: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pipeline: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_alias: Option<bool>,
+    /// Update only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_on_conflict: Option<u32>,
+}
+
+/// Body of an update action: a partial `doc` or a `script`, with an
+/// optional `upsert`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BulkUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doc: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upsert: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doc_as_upsert: Option<bool>,
+}
+
+impl BulkOperation {
+    /// Without an [`id`](BulkOperation::id), Elasticsearch generates one.
+    pub fn index<T: Serialize>(index: &str, source: &T) -> BulkOperation {
+        BulkOperation::Index {
+            meta: BulkMeta::new(index, None),
+            source: json!(source),
+        }
+    }
+    pub fn create<T: Serialize>(index: &str, id: &str, source: &T) -> BulkOperation {
+        BulkOperation::Create {
+            meta: BulkMeta::new(index, Some(id)),
+            source: json!(source),
+        }
+    }
+    /// Merges `doc` into the document.
+    pub fn update_doc<T: Serialize>(index: &str, id: &str, doc: &T) -> BulkOperation {
+        BulkOperation::Update {
+            meta: BulkMeta::new(index, Some(id)),
+            update: BulkUpdate {
+                doc: Some(json!(doc)),
+                ..Default::default()
+            },
+        }
+    }
+    /// Runs `script`, such as `{"source": "ctx._source.n += 1"}`.
+    pub fn update_script(index: &str, id: &str, script: Value) -> BulkOperation {
+        BulkOperation::Update {
+            meta: BulkMeta::new(index, Some(id)),
+            update: BulkUpdate {
+                script: Some(script),
+                ..Default::default()
+            },
+        }
+    }
+    pub fn delete(index: &str, id: &str) -> BulkOperation {
+        BulkOperation::Delete {
+            meta: BulkMeta::new(index, Some(id)),
+        }
+    }
+
+    pub fn meta(&self) -> &BulkMeta {
+        match self {
+            BulkOperation::Index { meta, .. }
+            | BulkOperation::Create { meta, .. }
+            | BulkOperation::Update { meta, .. }
+            | BulkOperation::Delete { meta } => meta,
+        }
+    }
+    fn meta_mut(&mut self) -> &mut BulkMeta {
+        match self {
+            BulkOperation::Index { meta, .. }
+            | BulkOperation::Create { meta, .. }
+            | BulkOperation::Update { meta, .. }
+            | BulkOperation::Delete { meta } => meta,
+        }
+    }
+
+    pub fn id(mut self, id: &str) -> Self {
+        self.meta_mut().id = Some(id.to_string());
+        self
+    }
+    pub fn routing(mut self, routing: &str) -> Self {
+        self.meta_mut().routing = Some(routing.to_string());
+        self
+    }
+    /// Applies only if the document was last written at this sequence
+    /// number and primary term.
+    pub fn if_seq_no(mut self, seq_no: i64, primary_term: i64) -> Self {
+        let meta = self.meta_mut();
+        meta.if_seq_no = Some(seq_no);
+        meta.if_primary_term = Some(primary_term);
+        self
+    }
+    pub fn version(mut self, version: i64, version_type: &str) -> Self {
+        let meta = self.meta_mut();
+        meta.version = Some(version);
+        meta.version_type = Some(version_type.to_string());
+        self
+    }
+    pub fn pipeline(mut self, pipeline: &str) -> Self {
+        self.meta_mut().pipeline = Some(pipeline.to_string());
+        self
+    }
+    /// Fails unless the index name is an alias.
+    pub fn require_alias(mut self, require_alias: bool) -> Self {
+        self.meta_mut().require_alias = Some(require_alias);
+        self
+    }
+    pub fn retry_on_conflict(mut self, retries: u32) -> Self {
+        self.meta_mut().retry_on_conflict = Some(retries);
+        self
+    }
+    /// Document to insert when an update finds none.
+    pub fn upsert<T: Serialize>(mut self, upsert: &T) -> Self {
+        if let BulkOperation::Update { update, .. } = &mut self {
+            update.upsert = Some(json!(upsert));
+        }
+        self
+    }
+    /// Inserts the update's `doc` when the document is missing.
+    pub fn doc_as_upsert(mut self, doc_as_upsert: bool) -> Self {
+        if let BulkOperation::Update { update, .. } = &mut self {
+            update.doc_as_upsert = Some(doc_as_upsert);
+        }
+        self
+    }
+
+    /// `index`, `create`, `update` or `delete`.
+    pub fn action_name(&self) -> &'static str {
+        match self {
+            BulkOperation::Index { .. } => "index",
+            BulkOperation::Create { .. } => "create",
+            BulkOperation::Update { .. } => "update",
+            BulkOperation::Delete { .. } => "delete",
+        }
+    }
+    pub fn action(&self) -> Value {
+        json!({ self.action_name(): self.meta() })
+    }
+    /// Body line; `None` for `delete`.
+    pub fn body(&self) -> Option<Value> {
+        match self {
+            BulkOperation::Index { source, .. } | BulkOperation::Create { source, .. } => {
+                Some(source.clone())
+            }
+            BulkOperation::Update { update, .. } => Some(json!(update)),
+            BulkOperation::Delete { .. } => None,
+        }
+    }
+    /// NDJSON lines of the operation.
+    pub fn lines(&self) -> Vec<Value> {
+        let mut lines = vec![self.action()];
+        lines.extend(self.body());
+        lines
+    }
+}
+
+impl BulkMeta {
+    fn new(index: &str, id: Option<&str>) -> BulkMeta {
+        BulkMeta {
+            index: Some(index.to_string()),
+            id: id.map(|v| v.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Parsed `_bulk` response. `items` follow the order of the request.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BulkResponse {
+    #[serde(default)]
+    pub took: u64,
+    /// Whether any item failed.
+    #[serde(default)]
+    pub errors: bool,
+    #[serde(default)]
+    pub items: Vec<BulkItem>,
+}
+
+impl BulkResponse {
+    pub fn failed_items(&self) -> Vec<&BulkItem> {
+        self.items.iter().filter(|v| !v.is_success()).collect()
+    }
+
+    /// Ids of the documents written, including those Elasticsearch generated.
+    pub fn successful_ids(&self) -> Vec<&str> {
+        self.items
+            .iter()
+            .filter(|v| v.is_success())
+            .filter_map(|v| v.id.as_deref())
+            .collect()
+    }
+
+    /// Pairs each failed item with the operation it answers.
+    pub fn failed_operations<'a>(
+        &'a self,
+        operations: &'a [BulkOperation],
+    ) -> Vec<(&'a BulkOperation, &'a BulkItem)> {
+        operations
+            .iter()
+            .zip(&self.items)
+            .filter(|(_, item)| !item.is_success())
+            .collect()
+    }
+
+    /// The failed items of a request sent as `lines`, with their action and
+    /// body.
+    pub(crate) fn failures(&self, lines: &[Value]) -> Vec<BulkFailure> {
+        split_lines(lines)
+            .into_iter()
+            .zip(&self.items)
+            .filter_map(|((action, source), item)| {
+                let error = item.error.clone()?;
+                Some(BulkFailure {
+                    action: action.clone(),
+                    source: source.cloned(),
+                    status: Some(item.status),
+                    error,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Outcome of [`BulkApi::operations_with_retry`].
+#[derive(Debug, Clone, Default)]
+pub struct BulkReport {
+    /// Bulk requests sent, retries included.
+    pub requests: u32,
+    /// Items written on the first attempt.
+    pub succeeded: Vec<BulkItem>,
+    /// Items rejected with a retryable status, then written.
+    pub retried: Vec<BulkItem>,
+    /// Items given up on, with their last error.
+    pub failed: Vec<BulkFailure>,
+}
+
+impl BulkReport {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    fn fail_all(
+        &mut self,
+        operations: Vec<(Value, Option<Value>)>,
+        status: Option<u16>,
+        error: ElasticError,
+    ) {
+        for (action, source) in operations {
+            self.failed.push(BulkFailure {
+                action,
+                source,
+                status,
+                error: error.clone(),
+            });
+        }
+    }
+}
+
+/// Result of one bulk action.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "HashMap<String, RawBulkItem>")]
+pub struct BulkItem {
+    /// `index`, `create`, `update` or `delete`.
+    pub action: String,
+    pub index: String,
+    pub id: Option<String>,
+    pub status: u16,
+    /// `created`, `updated`, `deleted`, `not_found` or `noop`.
+    pub result: Option<String>,
+    pub version: Option<i64>,
+    pub seq_no: Option<i64>,
+    pub primary_term: Option<i64>,
+    pub error: Option<ElasticError>,
+}
+
+impl BulkItem {
+    /// A delete of a missing document succeeds with status 404.
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+#[derive(Deserialize)]
+struct RawBulkItem {
+    #[serde(default)]
+    _index: String,
+    _id: Option<String>,
+    status: u16,
+    result: Option<String>,
+    _version: Option<i64>,
+    _seq_no: Option<i64>,
+    _primary_term: Option<i64>,
+    error: Option<Value>,
+}
+
+impl TryFrom<HashMap<String, RawBulkItem>> for BulkItem {
+    type Error = String;
+
+    fn try_from(value: HashMap<String, RawBulkItem>) -> Result<Self, Self::Error> {
+        let (action, item) = value
+            .into_iter()
+            .next()
+            .ok_or_else(|| "empty bulk item".to_string())?;
+        let error = item
+            .error
+            .map(|e| item_error(item.status, &json!({ "error": e })));
+        Ok(BulkItem {
+            action,
+            index: item._index,
+            id: item._id,
+            status: item.status,
+            result: item.result,
+            version: item._version,
+            seq_no: item._seq_no,
+            primary_term: item._primary_term,
+            error,
+        })
+    }
+}
+
+/// Splits bulk lines into actions and their bodies; `delete` has none.
+fn split_lines(lines: &[Value]) -> Vec<(&Value, Option<&Value>)> {
+    let mut operations = vec![];
+    let mut lines = lines.iter();
+    while let Some(action) = lines.next() {
+        let source = match action.get("delete") {
+            Some(_) => None,
+            None => lines.next(),
+        };
+        operations.push((action, source));
+    }
+    operations
+}
+
+/// A bulk action that could not be written.
+#[derive(Debug, Clone)]
+pub struct BulkFailure {
+    pub action: Value,
+    pub source: Option<Value>,
+    /// Item status, `None` when the whole request failed.
+    pub status: Option<u16>,
+    pub error: ElasticError,
+}
+
+type FailureHandler = Arc<dyn Fn(BulkFailure) + Send + Sync>;
+
+/// Counts of a [`BulkIngester`] so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BulkIngesterStats {
+    /// Bulk requests sent, retries included.
+    pub requests: u64,
+    /// Actions written.
+    pub succeeded: u64,
+    /// Actions written after being rejected at least once.
+    pub retried: u64,
+    /// Actions given up on and reported as [`BulkFailure`]s.
+    pub failed: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    requests: AtomicU64,
+    succeeded: AtomicU64,
+    retried: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl Counters {
+    fn stats(&self) -> BulkIngesterStats {
+        BulkIngesterStats {
+            requests: self.requests.load(Ordering::Relaxed),
+            succeeded: self.succeeded.load(Ordering::Relaxed),
+            retried: self.retried.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub struct BulkIngesterBuilder {
+    api: ElasticApi,
+    max_actions: usize,
+    max_bytes: usize,
+    flush_interval: Option<Duration>,
+    max_concurrent_requests: usize,
+    retry: RetryPolicy,
+    refresh: bool,
+    on_failure: Option<FailureHandler>,
+}
+
+impl BulkIngesterBuilder {
+    pub fn new(api: &ElasticApi) -> BulkIngesterBuilder {
+        BulkIngesterBuilder {
+            api: api.clone(),
+            max_actions: 1000,
+            max_bytes: 5 * 1024 * 1024,
+            flush_interval: None,
+            max_concurrent_requests: 1,
+            retry: RetryPolicy::default(),
+            refresh: false,
+            on_failure: None,
+        }
+    }
+    /// Flushes once this many actions are buffered. Default 1000.
+    pub fn max_actions(mut self, max_actions: usize) -> Self {
+        self.max_actions = max_actions.max(1);
+        self
+    }
+    /// Flushes once the buffered NDJSON reaches this size. Default 5 MiB.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes.max(1);
+        self
+    }
+    /// Also flushes on this period, so a slow producer does not hold
+    /// actions back. Off by default.
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = Some(interval);
+        self
+    }
+    /// Bulk requests in flight at once; further flushes wait, and so does
+    /// `add` once the buffer is full again. Default 1.
+    pub fn max_concurrent_requests(mut self, max: usize) -> Self {
+        self.max_concurrent_requests = max.max(1);
+        self
+    }
+    /// Backoff and statuses for resending rejected actions, as for
+    /// [`BulkApi::operations_with_retry`]. Only the rejected actions of a
+    /// response are sent again.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+    pub fn refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+    /// Called from the ingester's tasks for each action given up on.
+    pub fn on_failure<F>(mut self, f: F) -> Self
+    where
+        F: Fn(BulkFailure) + Send + Sync + 'static,
+    {
+        self.on_failure = Some(Arc::new(f));
+        self
+    }
+    /// Sends each action given up on to `sender`.
+    pub fn failure_channel(self, sender: mpsc::UnboundedSender<BulkFailure>) -> Self {
+        self.on_failure(move |failure| {
+            let _ = sender.send(failure);
+        })
+    }
+
+    /// Starts the background task. Needs a Tokio runtime.
+    pub fn build(self) -> BulkIngester {
+        let (tx, rx) = mpsc::channel(self.max_actions);
+        let config = Arc::new(Config {
+            api: self.api,
+            max_actions: self.max_actions,
+            max_bytes: self.max_bytes,
+            max_concurrent_requests: self.max_concurrent_requests,
+            retry: self.retry,
+            refresh: self.refresh,
+            on_failure: self.on_failure,
+            counters: Counters::default(),
+        });
+        let task = tokio::spawn(run(rx, config.clone(), self.flush_interval));
+        BulkIngester { tx, task, config }
+    }
+}
+
+/// Buffers bulk actions and sends them in the background when the buffer
+/// reaches [`max_actions`](BulkIngesterBuilder::max_actions) or
+/// [`max_bytes`](BulkIngesterBuilder::max_bytes), or on the
+/// [`flush_interval`](BulkIngesterBuilder::flush_interval).
+///
+/// Rejected actions (429, 503) are retried on their own; actions that
+/// still fail are reported to [`on_failure`](BulkIngesterBuilder::on_failure)
+/// and counted in [`stats`](BulkIngester::stats). Call
+/// [`close`](BulkIngester::close) to send what is left and wait for it.
+///
+/// ```no_run
+/// # async fn run(api: uiuifree_elastic::ElasticApi) {
+/// use serde_json::json;
+/// use std::time::Duration;
+///
+/// let ingester = api
+///     .bulk()
+///     .ingester()
+///     .max_actions(500)
+///     .flush_interval(Duration::from_secs(1))
+///     .max_concurrent_requests(2)
+///     .on_failure(|failure| eprintln!("{}", failure.error))
+///     .build();
+/// for i in 0..10_000 {
+///     let action = json!({"index": {"_index": "logs", "_id": i.to_string()}});
+///     ingester.add(action, Some(json!({"n": i}))).await.unwrap();
+/// }
+/// let stats = ingester.close().await.unwrap();
+/// # }
+/// ```
+pub struct BulkIngester {
+    tx: mpsc::Sender<Command>,
+    task: JoinHandle<()>,
+    config: Arc<Config>,
+}
+
+impl BulkIngester {
+    /// Buffers an action line and, unless it is a `delete`, its source line.
+    pub async fn add(&self, action: Value, source: Option<Value>) -> Result<(), ElasticError> {
+        let operation = Operation::new(action, source);
+        self.tx
+            .send(Command::Add(operation))
+            .await
+            .map_err(|_| ElasticError::Send("bulk ingester stopped".to_string()))
+    }
+
+    /// Buffers a typed operation.
+    pub async fn add_operation(&self, operation: BulkOperation) -> Result<(), ElasticError> {
+        self.add(operation.action(), operation.body()).await
+    }
+
+    /// Sends the buffered actions and waits until every request in flight
+    /// has completed.
+    pub async fn flush(&self) -> Result<(), ElasticError> {
+        let (done, wait) = oneshot::channel();
+        self.tx
+            .send(Command::Flush(done))
+            .await
+            .map_err(|_| ElasticError::Send("bulk ingester stopped".to_string()))?;
+        wait.await
+            .map_err(|_| ElasticError::Send("bulk ingester stopped".to_string()))
+    }
+
+    pub fn stats(&self) -> BulkIngesterStats {
+        self.config.counters.stats()
+    }
+
+    /// Flushes, waits for every request and stops the background task.
+    pub async fn close(self) -> Result<BulkIngesterStats, ElasticError> {
+        let BulkIngester { tx, task, config } = self;
+        drop(tx);
+        task.await.map_err(|e| ElasticError::Send(e.to_string()))?;
+        Ok(config.counters.stats())
+    }
+}
+
+struct Config {
+    api: ElasticApi,
+    max_actions: usize,
+    max_bytes: usize,
+    max_concurrent_requests: usize,
+    retry: RetryPolicy,
+    refresh: bool,
+    on_failure: Option<FailureHandler>,
+    counters: Counters,
+}
+
+impl Config {
+    fn report(&self, failure: BulkFailure) {
+        self.counters.failed.fetch_add(1, Ordering::Relaxed);
+        if let Some(f) = &self.on_failure {
+            f(failure);
+        }
+    }
+}
+
+enum Command {
+    Add(Operation),
+    Flush(oneshot::Sender<()>),
+}
+
+struct Operation {
+    action: Value,
+    source: Option<Value>,
+    bytes: usize,
+}
+
+impl Operation {
+    fn new(action: Value, source: Option<Value>) -> Operation {
+        // Each line plus its newline.
+        let bytes = action.to_string().len()
+            + 1
+            + source
+                .as_ref()
+                .map(|v| v.to_string().len() + 1)
+                .unwrap_or(0);
+        Operation {
+            action,
+            source,
+            bytes,
+        }
+    }
+}
+
+async fn run(
+    mut rx: mpsc::Receiver<Command>,
+    config: Arc<Config>,
+    flush_interval: Option<Duration>,
+) {
+    let permits = Arc::new(Semaphore::new(config.max_concurrent_requests));
+    let mut buffer: Vec<Operation> = vec![];
+    let mut bytes = 0;
+    let mut interval = flush_interval.map(|v| {
+        let mut interval = tokio::time::interval(v);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval
+    });
+    loop {
+        let tick = async {
+            match &mut interval {
+                Some(v) => {
+                    v.tick().await;
+                }
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            command = rx.recv() => match command {
+                Some(Command::Add(operation)) => {
+                    bytes += operation.bytes;
+                    buffer.push(operation);
+                    if buffer.len() >= config.max_actions || bytes >= config.max_bytes {
+                        flush(&config, &permits, &mut buffer).await;
+                        bytes = 0;
+                    }
+                }
+                Some(Command::Flush(done)) => {
+                    flush(&config, &permits, &mut buffer).await;
+                    bytes = 0;
+                    wait_idle(&config, &permits).await;
+                    let _ = done.send(());
+                }
+                None => break,
+            },
+            _ = tick => {
+                flush(&config, &permits, &mut buffer).await;
+                bytes = 0;
+            }
+        }
+    }
+    flush(&config, &permits, &mut buffer).await;
+    wait_idle(&config, &permits).await;
+}
+
+/// Sends the buffer once a request slot is free.
+async fn flush(config: &Arc<Config>, permits: &Arc<Semaphore>, buffer: &mut Vec<Operation>) {
+    if buffer.is_empty() {
+        return;
+    }
+    let operations = std::mem::take(buffer);
+    let permit = match permits.clone().acquire_owned().await {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    let config = config.clone();
+    tokio::spawn(async move {
+        send(&config, operations).await;
+        drop(permit);
+    });
+}
+
+async fn wait_idle(config: &Config, permits: &Semaphore) {
+    let _ = permits
+        .acquire_many(config.max_concurrent_requests as u32)
+        .await;
+}
+
+async fn send(config: &Config, operations: Vec<Operation>) {
+    let operations = operations
+        .into_iter()
+        .map(|v| (v.action, v.source))
+        .collect();
+    let report = send_retrying(&config.api, &config.retry, config.refresh, operations).await;
+    let counters = &config.counters;
+    counters
+        .requests
+        .fetch_add(report.requests as u64, Ordering::Relaxed);
+    counters.succeeded.fetch_add(
+        (report.succeeded.len() + report.retried.len()) as u64,
+        Ordering::Relaxed,
+    );
+    counters
+        .retried
+        .fetch_add(report.retried.len() as u64, Ordering::Relaxed);
+    for failure in report.failed {
+        config.report(failure);
+    }
+}
+
+/// Statuses at which Elasticsearch rejects a bulk request or item without
+/// writing it.
+const REJECTED_STATUS: [u16; 2] = [429, 503];
+
+/// Sends `operations`, then again only the items rejected with a retryable
+/// status, until they are written or `policy` runs out of attempts. Items
+/// that were written are never sent twice.
+async fn send_retrying(
+    api: &ElasticApi,
+    policy: &RetryPolicy,
+    refresh: bool,
+    mut operations: Vec<(Value, Option<Value>)>,
+) -> BulkReport {
+    let mut report = BulkReport::default();
+    let mut attempt = 1;
+    loop {
+        let mut lines = Vec::with_capacity(operations.len() * 2);
+        for (action, source) in &operations {
+            lines.push(action.clone());
+            lines.extend(source.clone());
+        }
+        report.requests += 1;
+        // Sent once: this loop owns the attempts and the backoff.
+        let res = api
+            .send_non_idempotent(&None, |client| {
+                let lines = &lines;
+                async move {
+                    client
+                        .bulk(BulkParts::None)
+                        .body(ndjson_body(lines))
+                        .refresh(bool_to_refresh(refresh))
+                        .send()
+                        .await
+                }
+            })
+            .await;
+        let res: BulkResponse = match parse_response(res).await {
+            Ok(v) => v,
+            Err(e) if attempt < policy.max_attempts && resendable(policy, &e, &lines) => {
+                tokio::time::sleep(policy.delay(attempt)).await;
+                attempt += 1;
+                continue;
+            }
+            Err(e) => {
+                report.fail_all(operations, e.status(), e);
+                return report;
+            }
+        };
+        if res.items.len() != operations.len() {
+            let e = ElasticError::Response(format!(
+                "{} bulk items for {} actions",
+                res.items.len(),
+                operations.len()
+            ));
+            report.fail_all(operations, None, e);
+            return report;
+        }
+
+        let mut rejected = vec![];
+        for ((action, source), item) in operations.into_iter().zip(res.items) {
+            match item.error {
+                None if attempt == 1 => report.succeeded.push(item),
+                None => report.retried.push(item),
+                Some(_)
+                    if rejected_status(policy, item.status) && attempt < policy.max_attempts =>
+                {
+                    rejected.push((action, source));
+                }
+                Some(error) => report.failed.push(BulkFailure {
+                    action,
+                    source,
+                    status: Some(item.status),
+                    error,
+                }),
+            }
+        }
+        if rejected.is_empty() {
+            return report;
+        }
+        tokio::time::sleep(policy.delay(attempt)).await;
+        attempt += 1;
+        operations = rejected;
+    }
+}
+
+fn rejected_status(policy: &RetryPolicy, status: u16) -> bool {
+    REJECTED_STATUS.contains(&status) && policy.is_retryable_status(status)
+}
+
+/// Whether a failed bulk request can be sent again whole. A rejection wrote
+/// nothing; a gateway error or a lost connection may hide a written batch.
+fn resendable(policy: &RetryPolicy, error: &ElasticError, lines: &[Value]) -> bool {
+    let retryable = match error {
+        ElasticError::Connection(_) => policy.retry_on_connection_error,
+        e => match e.status() {
+            Some(status) if rejected_status(policy, status) => return true,
+            Some(status) => policy.is_retryable_status(status),
+            None => false,
+        },
+    };
+    retryable && bulk_is_idempotent(lines)
+}
+
+/// The `error` of a bulk response item as an [`ElasticError`].
+fn item_error(status: u16, item: &Value) -> ElasticError {
+    let body = json!({ "error": item["error"], "status": status }).to_string();
+    ElasticError::from_status(status, body)
+}
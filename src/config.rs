@@ -0,0 +1,567 @@
+use crate::error::ElasticError;
+use crate::pool::{MultiNodeConnectionPool, PoolSettings};
+use crate::retry::RetryPolicy;
+use crate::tls::TlsConfig;
+use crate::{ElasticApi, ElasticClient};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use dotenv::dotenv;
+use elasticsearch::auth::Credentials;
+use elasticsearch::http::headers::{HeaderName, HeaderValue, AUTHORIZATION};
+use elasticsearch::http::transport::{
+    CloudConnectionPool, SingleNodeConnectionPool, TransportBuilder,
+};
+use elasticsearch::http::Url;
+use elasticsearch::Elasticsearch;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const DEFAULT_HOST: &str = "http://localhost:9200";
+
+/// Connection settings, loadable from `ELASTIC_*` env vars or a TOML file.
+///
+/// ```toml
+/// nodes = ["https://es01:9200", "https://es02:9200"]
+/// username = "elastic"
+/// password = "changeme"
+/// timeout_ms = 30000
+///
+/// [headers]
+/// x-opaque-id = "batch"
+///
+/// [tls]
+/// ca_cert = "/etc/elasticsearch/certs/http_ca.crt"
+///
+/// [retry]
+/// max_attempts = 5
+/// ```
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ElasticConfig {
+    pub nodes: Vec<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// API key secret. Used together with `api_key_id`, or on its own as the
+    /// base64 encoded `id:api_key` value Kibana hands out.
+    pub api_key: Option<String>,
+    pub api_key_id: Option<String>,
+    pub bearer_token: Option<String>,
+    pub cloud_id: Option<String>,
+    pub timeout_ms: Option<u64>,
+    pub proxy: Option<String>,
+    pub proxy_username: Option<String>,
+    pub proxy_password: Option<String>,
+    pub headers: BTreeMap<String, String>,
+    pub tls: TlsConfig,
+    pub dead_timeout_ms: Option<u64>,
+    pub max_dead_timeout_ms: Option<u64>,
+    pub sniff_on_start: bool,
+    pub sniff_interval_ms: Option<u64>,
+    pub sniff_on_connection_fault: bool,
+    /// Retry policy for idempotent operations. No retries when unset.
+    pub retry: Option<RetryPolicy>,
+}
+
+/// Secrets are printed as `"<redacted>"`.
+impl std::fmt::Debug for ElasticConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ElasticConfig")
+            .field("nodes", &self.nodes)
+            .field("username", &self.username)
+            .field("password", &redact(&self.password))
+            .field("api_key", &redact(&self.api_key))
+            .field("api_key_id", &self.api_key_id)
+            .field("bearer_token", &redact(&self.bearer_token))
+            .field("cloud_id", &self.cloud_id)
+            .field("timeout_ms", &self.timeout_ms)
+            .field("proxy", &self.proxy)
+            .field("proxy_username", &self.proxy_username)
+            .field("proxy_password", &redact(&self.proxy_password))
+            .field("headers", &self.headers)
+            .field("tls", &self.tls)
+            .field("dead_timeout_ms", &self.dead_timeout_ms)
+            .field("max_dead_timeout_ms", &self.max_dead_timeout_ms)
+            .field("sniff_on_start", &self.sniff_on_start)
+            .field("sniff_interval_ms", &self.sniff_interval_ms)
+            .field("sniff_on_connection_fault", &self.sniff_on_connection_fault)
+            .field("retry", &self.retry)
+            .finish()
+    }
+}
+
+pub(crate) fn redact(secret: &Option<String>) -> Option<&'static str> {
+    secret.as_ref().map(|_| "<redacted>")
+}
+
+impl ElasticConfig {
+    /// Reads `ELASTIC_CONFIG` (TOML path) first when set, then applies the
+    /// other `ELASTIC_*` variables on top of it.
+    ///
+    /// | variable | value |
+    /// |---|---|
+    /// | `ELASTIC_HOST` | node url, comma separated for several nodes |
+    /// | `ELASTIC_USERNAME` / `ELASTIC_PASSWORD` | basic auth |
+    /// | `ELASTIC_API_KEY_ID` / `ELASTIC_API_KEY` | api key |
+    /// | `ELASTIC_BEARER_TOKEN` | bearer token |
+    /// | `ELASTIC_CLOUD_ID` | Elastic Cloud id |
+    /// | `ELASTIC_TIMEOUT_MS` | request timeout |
+    /// | `ELASTIC_PROXY` / `ELASTIC_PROXY_USERNAME` / `ELASTIC_PROXY_PASSWORD` | proxy |
+    /// | `ELASTIC_HEADERS` | `name=value,name2=value2` |
+    /// | `ELASTIC_CA_CERT` | PEM CA bundle path |
+    /// | `ELASTIC_CLIENT_CERT` / `ELASTIC_CLIENT_KEY` | PEM client certificate and key paths |
+    /// | `ELASTIC_CLIENT_P12` / `ELASTIC_CLIENT_P12_PASSWORD` | PKCS#12 client identity |
+    /// | `ELASTIC_CA_FINGERPRINT` | SHA-256 fingerprint of the CA |
+    /// | `ELASTIC_TLS_INSECURE` | `true` disables certificate validation |
+    /// | `ELASTIC_SNIFF_ON_START` / `ELASTIC_SNIFF_ON_CONNECTION_FAULT` | `true` enables sniffing |
+    /// | `ELASTIC_SNIFF_INTERVAL_MS` | periodic sniffing |
+    /// | `ELASTIC_DEAD_TIMEOUT_MS` / `ELASTIC_MAX_DEAD_TIMEOUT_MS` | dead node back-off |
+    /// | `ELASTIC_RETRY_MAX_ATTEMPTS` | retries idempotent operations with the default back-off |
+    pub fn from_env() -> Result<ElasticConfig, ElasticError> {
+        dotenv().ok();
+        let mut config = match env::var("ELASTIC_CONFIG") {
+            Ok(path) => ElasticConfig::from_toml_file(path)?,
+            Err(_) => ElasticConfig::default(),
+        };
+        if let Ok(v) = env::var("ELASTIC_HOST") {
+            config.nodes = split_list(&v);
+        }
+        set_from_env(&mut config.username, "ELASTIC_USERNAME");
+        set_from_env(&mut config.password, "ELASTIC_PASSWORD");
+        set_from_env(&mut config.api_key, "ELASTIC_API_KEY");
+        set_from_env(&mut config.api_key_id, "ELASTIC_API_KEY_ID");
+        set_from_env(&mut config.bearer_token, "ELASTIC_BEARER_TOKEN");
+        set_from_env(&mut config.cloud_id, "ELASTIC_CLOUD_ID");
+        set_from_env(&mut config.proxy, "ELASTIC_PROXY");
+        set_from_env(&mut config.proxy_username, "ELASTIC_PROXY_USERNAME");
+        set_from_env(&mut config.proxy_password, "ELASTIC_PROXY_PASSWORD");
+        set_path_from_env(&mut config.tls.ca_cert, "ELASTIC_CA_CERT");
+        set_path_from_env(&mut config.tls.client_cert, "ELASTIC_CLIENT_CERT");
+        set_path_from_env(&mut config.tls.client_key, "ELASTIC_CLIENT_KEY");
+        set_path_from_env(&mut config.tls.client_p12, "ELASTIC_CLIENT_P12");
+        set_from_env(
+            &mut config.tls.client_p12_password,
+            "ELASTIC_CLIENT_P12_PASSWORD",
+        );
+        set_from_env(&mut config.tls.ca_fingerprint, "ELASTIC_CA_FINGERPRINT");
+        set_bool_from_env(&mut config.tls.insecure, "ELASTIC_TLS_INSECURE");
+        set_bool_from_env(&mut config.sniff_on_start, "ELASTIC_SNIFF_ON_START");
+        set_bool_from_env(
+            &mut config.sniff_on_connection_fault,
+            "ELASTIC_SNIFF_ON_CONNECTION_FAULT",
+        );
+        set_millis_from_env(&mut config.timeout_ms, "ELASTIC_TIMEOUT_MS")?;
+        set_millis_from_env(&mut config.sniff_interval_ms, "ELASTIC_SNIFF_INTERVAL_MS")?;
+        set_millis_from_env(&mut config.dead_timeout_ms, "ELASTIC_DEAD_TIMEOUT_MS")?;
+        set_millis_from_env(
+            &mut config.max_dead_timeout_ms,
+            "ELASTIC_MAX_DEAD_TIMEOUT_MS",
+        )?;
+        if let Ok(v) = env::var("ELASTIC_RETRY_MAX_ATTEMPTS") {
+            let attempts = v.parse().map_err(|_| {
+                ElasticError::Config(format!("ELASTIC_RETRY_MAX_ATTEMPTS is not a number: {}", v))
+            })?;
+            config
+                .retry
+                .get_or_insert_with(RetryPolicy::default)
+                .max_attempts = attempts;
+        }
+        if let Ok(v) = env::var("ELASTIC_HEADERS") {
+            for pair in split_list(&v) {
+                match pair.split_once('=') {
+                    Some((name, value)) => {
+                        config
+                            .headers
+                            .insert(name.trim().to_string(), value.trim().to_string());
+                    }
+                    None => {
+                        return Err(ElasticError::Config(format!(
+                            "ELASTIC_HEADERS entry is not name=value: {}",
+                            pair
+                        )))
+                    }
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn from_toml_file<P: AsRef<Path>>(path: P) -> Result<ElasticConfig, ElasticError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| ElasticError::Config(format!("{}: {}", path.display(), e)))?;
+        ElasticConfig::from_toml_str(&text)
+    }
+
+    pub fn from_toml_str(text: &str) -> Result<ElasticConfig, ElasticError> {
+        toml::from_str(text).map_err(|e| ElasticError::Config(e.to_string()))
+    }
+
+    pub fn build(self) -> Result<ElasticApi, ElasticError> {
+        ClientBuilder::from_config(self).build()
+    }
+}
+
+fn set_from_env(target: &mut Option<String>, key: &str) {
+    if let Ok(v) = env::var(key) {
+        if !v.is_empty() {
+            *target = Some(v);
+        }
+    }
+}
+
+fn set_bool_from_env(target: &mut bool, key: &str) {
+    if let Ok(v) = env::var(key) {
+        *target = matches!(v.trim(), "1" | "true" | "TRUE" | "yes");
+    }
+}
+
+fn set_millis_from_env(target: &mut Option<u64>, key: &str) -> Result<(), ElasticError> {
+    if let Ok(v) = env::var(key) {
+        *target = Some(
+            v.trim()
+                .parse()
+                .map_err(|_| ElasticError::Config(format!("{} is not a number: {}", key, v)))?,
+        );
+    }
+    Ok(())
+}
+
+fn set_path_from_env(target: &mut Option<PathBuf>, key: &str) {
+    if let Ok(v) = env::var(key) {
+        if !v.is_empty() {
+            *target = Some(PathBuf::from(v));
+        }
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+        .collect()
+}
+
+fn authorization_value(credentials: &Credentials) -> String {
+    match credentials {
+        Credentials::Basic(username, password) => {
+            format!(
+                "Basic {}",
+                STANDARD.encode(format!("{}:{}", username, password))
+            )
+        }
+        Credentials::ApiKey(id, key) => {
+            format!("ApiKey {}", STANDARD.encode(format!("{}:{}", id, key)))
+        }
+        Credentials::Bearer(token) => format!("Bearer {}", token),
+        Credentials::Certificate(_) => String::new(),
+    }
+}
+
+fn parse_url(url: &str) -> Result<Url, ElasticError> {
+    Url::parse(url).map_err(|e| ElasticError::Config(format!("invalid url {}: {}", url, e)))
+}
+
+/// Fluent construction of an [`ElasticApi`].
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use uiuifree_elastic::ClientBuilder;
+///
+/// let api = ClientBuilder::new()
+///     .nodes(["http://es01:9200", "http://es02:9200"])
+///     .basic_auth("elastic", "changeme")
+///     .timeout(Duration::from_secs(30))
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct ClientBuilder {
+    config: ElasticConfig,
+}
+
+impl ClientBuilder {
+    pub fn new() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+    pub fn from_config(config: ElasticConfig) -> ClientBuilder {
+        ClientBuilder { config }
+    }
+    pub fn from_env() -> Result<ClientBuilder, ElasticError> {
+        Ok(ClientBuilder::from_config(ElasticConfig::from_env()?))
+    }
+    pub fn config(&self) -> &ElasticConfig {
+        &self.config
+    }
+    pub fn node(mut self, url: &str) -> Self {
+        self.config.nodes.push(url.to_string());
+        self
+    }
+    pub fn nodes<I, S>(mut self, urls: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.nodes.extend(urls.into_iter().map(|v| v.into()));
+        self
+    }
+    pub fn basic_auth(mut self, username: &str, password: &str) -> Self {
+        self.config.username = Some(username.to_string());
+        self.config.password = Some(password.to_string());
+        self
+    }
+    pub fn api_key(mut self, id: &str, api_key: &str) -> Self {
+        self.config.api_key_id = Some(id.to_string());
+        self.config.api_key = Some(api_key.to_string());
+        self
+    }
+    /// Base64 encoded `id:api_key`, as shown by Kibana.
+    pub fn encoded_api_key(mut self, encoded: &str) -> Self {
+        self.config.api_key_id = None;
+        self.config.api_key = Some(encoded.to_string());
+        self
+    }
+    pub fn bearer_token(mut self, token: &str) -> Self {
+        self.config.bearer_token = Some(token.to_string());
+        self
+    }
+    pub fn cloud_id(mut self, cloud_id: &str) -> Self {
+        self.config.cloud_id = Some(cloud_id.to_string());
+        self
+    }
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout_ms = Some(timeout.as_millis() as u64);
+        self
+    }
+    pub fn proxy(mut self, url: &str) -> Self {
+        self.config.proxy = Some(url.to_string());
+        self
+    }
+    pub fn proxy_auth(mut self, username: &str, password: &str) -> Self {
+        self.config.proxy_username = Some(username.to_string());
+        self.config.proxy_password = Some(password.to_string());
+        self
+    }
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.config
+            .headers
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+    pub fn ca_cert<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.tls.ca_cert = Some(path.into());
+        self
+    }
+    pub fn client_cert<P: Into<PathBuf>>(mut self, cert: P, key: P) -> Self {
+        self.config.tls.client_cert = Some(cert.into());
+        self.config.tls.client_key = Some(key.into());
+        self
+    }
+    pub fn client_p12<P: Into<PathBuf>>(mut self, path: P, password: Option<&str>) -> Self {
+        self.config.tls.client_p12 = Some(path.into());
+        self.config.tls.client_p12_password = password.map(|v| v.to_string());
+        self
+    }
+    /// Trusts the CA with this SHA-256 fingerprint. The build connects to
+    /// the https nodes to fetch it, a blocking handshake with a 10 s
+    /// connect timeout per node. A multi-thread runtime keeps running
+    /// meanwhile, a current-thread one stalls: use
+    /// [`build_async`](ClientBuilder::build_async) there.
+    pub fn ca_fingerprint(mut self, fingerprint: &str) -> Self {
+        self.config.tls.ca_fingerprint = Some(fingerprint.to_string());
+        self
+    }
+    pub fn dead_timeout(mut self, initial: Duration, max: Duration) -> Self {
+        self.config.dead_timeout_ms = Some(initial.as_millis() as u64);
+        self.config.max_dead_timeout_ms = Some(max.as_millis() as u64);
+        self
+    }
+    pub fn sniff_on_start(mut self, enable: bool) -> Self {
+        self.config.sniff_on_start = enable;
+        self
+    }
+    pub fn sniff_interval(mut self, interval: Duration) -> Self {
+        self.config.sniff_interval_ms = Some(interval.as_millis() as u64);
+        self
+    }
+    pub fn sniff_on_connection_fault(mut self, enable: bool) -> Self {
+        self.config.sniff_on_connection_fault = enable;
+        self
+    }
+    /// Disables certificate validation. Development only.
+    pub fn insecure(mut self, insecure: bool) -> Self {
+        self.config.tls.insecure = insecure;
+        self
+    }
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.config.retry = Some(policy);
+        self
+    }
+
+    fn credentials(&self) -> Result<Option<Credentials>, ElasticError> {
+        let config = &self.config;
+        let mut found = vec![];
+        if let Some(username) = &config.username {
+            found.push(Credentials::Basic(
+                username.to_string(),
+                config.password.clone().unwrap_or_default(),
+            ));
+        }
+        if let (Some(id), Some(key)) = (&config.api_key_id, &config.api_key) {
+            found.push(Credentials::ApiKey(id.to_string(), key.to_string()));
+        }
+        if let Some(token) = &config.bearer_token {
+            found.push(Credentials::Bearer(token.to_string()));
+        }
+        let encoded_api_key = config.api_key.is_some() && config.api_key_id.is_none();
+        if found.len() + encoded_api_key as usize > 1 {
+            return Err(ElasticError::Config(
+                "only one of basic auth, api key or bearer token can be set".to_string(),
+            ));
+        }
+        Ok(found.pop())
+    }
+
+    /// The transport alone. Dead node tracking, sniffing and retries are
+    /// done by [`ElasticApi`], so they are lost here; prefer
+    /// [`build_client`](ClientBuilder::build_client).
+    pub fn build_transport(&self) -> Result<TransportBuilder, ElasticError> {
+        Ok(self.transport()?.0)
+    }
+
+    fn pool_settings(&self) -> PoolSettings {
+        let config = &self.config;
+        let defaults = PoolSettings::default();
+        PoolSettings {
+            dead_timeout: config
+                .dead_timeout_ms
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.dead_timeout),
+            max_dead_timeout: config
+                .max_dead_timeout_ms
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.max_dead_timeout),
+            sniff_on_start: config.sniff_on_start,
+            sniff_interval: config.sniff_interval_ms.map(Duration::from_millis),
+            sniff_on_connection_fault: config.sniff_on_connection_fault,
+        }
+    }
+
+    fn sniffing(&self) -> bool {
+        let config = &self.config;
+        config.sniff_on_start
+            || config.sniff_interval_ms.is_some()
+            || config.sniff_on_connection_fault
+    }
+
+    fn transport(
+        &self,
+    ) -> Result<(TransportBuilder, Option<MultiNodeConnectionPool>), ElasticError> {
+        let config = &self.config;
+        let mut pool = None;
+        let mut urls = config
+            .nodes
+            .iter()
+            .map(|v| parse_url(v))
+            .collect::<Result<Vec<Url>, ElasticError>>()?;
+        let mut builder = match &config.cloud_id {
+            Some(cloud_id) => {
+                if !urls.is_empty() {
+                    return Err(ElasticError::Config(
+                        "cloud_id and nodes cannot be used together".to_string(),
+                    ));
+                }
+                let pool = CloudConnectionPool::new(cloud_id)
+                    .map_err(|e| ElasticError::Config(e.to_string()))?;
+                TransportBuilder::new(pool)
+            }
+            None => {
+                if urls.is_empty() {
+                    urls.push(parse_url(DEFAULT_HOST)?);
+                }
+                if urls.len() == 1 && !self.sniffing() {
+                    TransportBuilder::new(SingleNodeConnectionPool::new(urls[0].clone()))
+                } else {
+                    let multi =
+                        MultiNodeConnectionPool::with_settings(urls.clone(), self.pool_settings())?;
+                    pool = Some(multi.clone());
+                    TransportBuilder::new(multi)
+                }
+            }
+        };
+
+        if let Some(validation) = config.tls.validation(&urls)? {
+            builder = builder.cert_validation(validation);
+        }
+        // The transport holds a single credential, so when a client
+        // certificate takes that slot the other credentials travel as a
+        // default Authorization header instead.
+        let mut authorization = None;
+        match (config.tls.identity()?, self.credentials()?) {
+            (Some(identity), credentials) => {
+                builder = builder.auth(Credentials::Certificate(identity));
+                authorization = credentials.map(|v| authorization_value(&v));
+            }
+            (None, Some(credentials)) => builder = builder.auth(credentials),
+            (None, None) => {}
+        }
+        if authorization.is_none() && config.api_key_id.is_none() {
+            if let Some(encoded) = &config.api_key {
+                authorization = Some(format!("ApiKey {}", encoded));
+            }
+        }
+        if let Some(value) = authorization {
+            let value =
+                HeaderValue::from_str(&value).map_err(|e| ElasticError::Config(e.to_string()))?;
+            builder = builder.header(AUTHORIZATION, value);
+        }
+        if let Some(ms) = config.timeout_ms {
+            builder = builder.timeout(Duration::from_millis(ms));
+        }
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(
+                parse_url(proxy)?,
+                config.proxy_username.as_deref(),
+                config.proxy_password.as_deref(),
+            );
+        }
+        for (name, value) in &config.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| ElasticError::Config(format!("header {}: {}", name, e)))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| ElasticError::Config(format!("header {}: {}", name, e)))?;
+            builder = builder.header(name, value);
+        }
+        Ok((builder, pool))
+    }
+
+    /// The client with its pool and retry policy, for [`ElasticApi::new`].
+    pub fn build_client(&self) -> Result<ElasticClient, ElasticError> {
+        let (builder, pool) = self.transport()?;
+        let transport = builder
+            .build()
+            .map_err(|e| ElasticError::Connection(e.to_string()))?;
+        Ok(ElasticClient {
+            client: Elasticsearch::new(transport),
+            pool,
+            retry: self.config.retry.clone(),
+        })
+    }
+
+    pub fn build(self) -> Result<ElasticApi, ElasticError> {
+        Ok(ElasticApi::new(self.build_client()?))
+    }
+
+    /// [`build`](ClientBuilder::build) on a blocking thread, so the
+    /// [`ca_fingerprint`](ClientBuilder::ca_fingerprint) handshake does not
+    /// stall the runtime, whatever its flavor.
+    pub async fn build_async(self) -> Result<ElasticApi, ElasticError> {
+        tokio::task::spawn_blocking(move || self.build())
+            .await
+            .map_err(|e| ElasticError::Config(e.to_string()))?
+    }
+}
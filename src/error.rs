@@ -1,6 +1,10 @@
+use crate::bulk::BulkFailure;
+use crate::transfer::ImportFailure;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::fmt::Display;
 use std::fmt::Formatter;
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ElasticError {
     Connection(String),
     Send(String),
@@ -8,6 +12,18 @@ pub enum ElasticError {
     Status(u16, String),
     Response(String),
     NotFound(String),
+    Config(String),
+    /// Reading or writing a file failed.
+    Io(String),
+    /// Error body returned by Elasticsearch.
+    Api(Box<ApiError>),
+    /// Error of one document in a multi-document response, which carries no
+    /// status of its own.
+    Item(Box<ErrorCause>),
+    /// Bulk items that failed while the request itself succeeded.
+    BulkPartialFailure(Vec<BulkFailure>),
+    /// Documents of an import that could not be written.
+    ImportFailure(Box<ImportFailure>),
 }
 
 impl ElasticError {
@@ -21,6 +37,86 @@ impl ElasticError {
             ElasticError::Status(_, e) => Some(e.to_string()),
             ElasticError::Send(e) => Some(e.to_string()),
             ElasticError::NotFound(e) => Some(e.to_string()),
+            ElasticError::Config(e) => Some(e.to_string()),
+            ElasticError::Io(e) => Some(e.to_string()),
+            ElasticError::Api(e) => Some(e.to_string()),
+            ElasticError::Item(e) => Some(match &e.reason {
+                Some(reason) => format!("{}: {}", e.error_type, reason),
+                None => e.error_type.to_string(),
+            }),
+            ElasticError::BulkPartialFailure(e) => Some(match e.first() {
+                Some(first) => format!("{} bulk items failed, first: {}", e.len(), first.error),
+                None => "bulk items failed".to_string(),
+            }),
+            ElasticError::ImportFailure(e) => Some(match e.failures.first() {
+                Some(first) => format!(
+                    "{} documents failed to import, first: {}",
+                    e.stats.failed, first.error
+                ),
+                None => format!("{} documents failed to import", e.stats.failed),
+            }),
+        }
+    }
+
+    /// `Api` when `body` is an Elasticsearch error document, `Status` otherwise.
+    pub fn from_status(status: u16, body: String) -> ElasticError {
+        match ApiError::parse(status, &body) {
+            Some(v) => ElasticError::Api(Box::new(v)),
+            None => ElasticError::Status(status, body),
+        }
+    }
+
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ElasticError::Status(status, _) => Some(*status),
+            ElasticError::Api(e) => Some(e.status),
+            _ => None,
+        }
+    }
+
+    pub fn api_error(&self) -> Option<&ApiError> {
+        match self {
+            ElasticError::Api(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn is_version_conflict(&self) -> bool {
+        self.has_type("version_conflict_engine_exception")
+    }
+
+    pub fn is_index_not_found(&self) -> bool {
+        self.has_type("index_not_found_exception")
+    }
+
+    /// The document did not match the mapping, or a mapping change was rejected.
+    pub fn is_mapping_error(&self) -> bool {
+        self.has_type("mapper_parsing_exception")
+            || self.has_type("document_parsing_exception")
+            || self.has_type("strict_dynamic_mapping_exception")
+            || self.has_type("mapper_exception")
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, ElasticError::NotFound(_)) || self.status() == Some(404)
+    }
+
+    /// Rejections and unavailable nodes that may succeed when sent again.
+    pub fn is_retryable(&self) -> bool {
+        if self.has_type("es_rejected_execution_exception") {
+            return true;
+        }
+        matches!(self, ElasticError::Connection(_))
+            || matches!(self.status(), Some(429 | 502 | 503 | 504))
+    }
+}
+
+impl ElasticError {
+    fn has_type(&self, error_type: &str) -> bool {
+        match self {
+            ElasticError::Api(e) => e.has_type(error_type),
+            ElasticError::Item(e) => e.has_type(error_type),
+            _ => false,
         }
     }
 }
@@ -31,3 +127,138 @@ impl Display for ElasticError {
         write!(f, "{}", error.unwrap_or_default())
     }
 }
+
+impl std::error::Error for ElasticError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ElasticError::Api(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// `error` object of an Elasticsearch error response.
+///
+/// ```json
+/// {"error": {"type": "index_not_found_exception", "reason": "no such index [a]",
+///   "index": "a", "root_cause": [...]}, "status": 404}
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiError {
+    pub status: u16,
+    #[serde(rename = "type")]
+    pub error_type: String,
+    pub reason: Option<String>,
+    pub root_cause: Vec<ErrorCause>,
+    pub caused_by: Option<Box<ErrorCause>>,
+    pub index: Option<String>,
+    pub shard: Option<String>,
+    /// Raw response body.
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorCause {
+    #[serde(rename = "type")]
+    pub error_type: String,
+    pub reason: Option<String>,
+    pub index: Option<String>,
+    pub shard: Option<String>,
+    pub caused_by: Option<Box<ErrorCause>>,
+}
+
+impl ErrorCause {
+    /// Whether the error or a nested cause has this type.
+    pub fn has_type(&self, error_type: &str) -> bool {
+        let mut cause = Some(self);
+        while let Some(v) = cause {
+            if v.error_type == error_type {
+                return true;
+            }
+            cause = v.caused_by.as_deref();
+        }
+        false
+    }
+
+    pub(crate) fn from_value(value: &Value) -> Option<ErrorCause> {
+        Some(ErrorCause {
+            error_type: value["type"].as_str()?.to_string(),
+            reason: string_field(value, "reason"),
+            index: string_field(value, "index"),
+            shard: string_field(value, "shard"),
+            caused_by: ErrorCause::from_value(&value["caused_by"]).map(Box::new),
+        })
+    }
+}
+
+impl ApiError {
+    /// Parses an error response body. `None` when the body carries no
+    /// `error` object, such as a 404 from the get API.
+    pub fn parse(status: u16, body: &str) -> Option<ApiError> {
+        let value: Value = serde_json::from_str(body).ok()?;
+        let error = &value["error"];
+        let status = value["status"].as_u64().map(|v| v as u16).unwrap_or(status);
+        let cause = match error {
+            // Some endpoints answer with `"error": "message"`.
+            Value::String(reason) => ErrorCause {
+                error_type: String::new(),
+                reason: Some(reason.to_string()),
+                index: None,
+                shard: None,
+                caused_by: None,
+            },
+            _ => ErrorCause::from_value(error)?,
+        };
+        let root_cause = match error["root_cause"].as_array() {
+            Some(v) => v.iter().filter_map(ErrorCause::from_value).collect(),
+            None => vec![],
+        };
+        Some(ApiError {
+            status,
+            error_type: cause.error_type,
+            reason: cause.reason,
+            root_cause,
+            caused_by: cause.caused_by,
+            index: cause.index,
+            shard: cause.shard,
+            body: body.to_string(),
+        })
+    }
+
+    /// Whether the error, a root cause or a nested cause has this type.
+    pub fn has_type(&self, error_type: &str) -> bool {
+        if self.error_type == error_type {
+            return true;
+        }
+        if self.root_cause.iter().any(|v| v.error_type == error_type) {
+            return true;
+        }
+        let mut cause = self.caused_by.as_deref();
+        while let Some(v) = cause {
+            if v.error_type == error_type {
+                return true;
+            }
+            cause = v.caused_by.as_deref();
+        }
+        false
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.reason {
+            Some(reason) => write!(f, "[{}] {}: {}", self.status, self.error_type, reason),
+            None => write!(f, "[{}] {}", self.status, self.error_type),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+fn string_field(value: &Value, key: &str) -> Option<String> {
+    match &value[key] {
+        Value::String(v) => Some(v.to_string()),
+        Value::Number(v) => Some(v.to_string()),
+        _ => None,
+    }
+}
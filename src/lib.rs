@@ -1,49 +1,84 @@
+pub mod bulk;
+pub mod config;
 pub mod error;
+pub mod mapping;
+pub mod paging;
+pub mod pool;
+pub mod repository;
+pub mod retry;
+pub mod tls;
+pub mod transfer;
 
-use crate::error::ElasticError;
-use dotenv::dotenv;
+use crate::error::{ElasticError, ErrorCause};
 use elastic_parser::{Doc, Hit, SearchResponse, Shards};
 use elastic_query_builder::QueryBuilder;
 use elasticsearch::http::request::JsonBody;
 use elasticsearch::http::response::Response;
 pub use elasticsearch::http::transport::*;
-use elasticsearch::http::transport::{SingleNodeConnectionPool, Transport};
-use elasticsearch::indices::{IndicesCreateParts, IndicesDeleteParts, IndicesExistsAlias, IndicesExistsAliasParts, IndicesExistsIndexTemplate, IndicesExistsIndexTemplateParts, IndicesExistsParts, IndicesExistsTemplateParts, IndicesGetAliasParts, IndicesPutIndexTemplateParts, IndicesRefreshParts};
+use elasticsearch::indices::{
+    IndicesCreateParts, IndicesDeleteParts, IndicesExistsAliasParts,
+    IndicesExistsIndexTemplateParts, IndicesExistsParts, IndicesGetAliasParts,
+    IndicesGetMappingParts, IndicesPutIndexTemplateParts, IndicesRefreshParts,
+};
 pub use elasticsearch::Elasticsearch;
 use elasticsearch::{
-    BulkParts, DeleteByQueryParts, DeleteParts, Error, GetParts, GetSourceParts, IndexParts,
-    ScrollParts, SearchParts, UpdateByQueryParts, UpdateParts,
+    BulkParts, CountParts, DeleteByQueryParts, DeleteParts, Error, ExistsParts, GetParts,
+    GetSourceParts, IndexParts, MgetParts, MsearchParts, ScrollParts, SearchParts,
+    UpdateByQueryParts, UpdateParts,
 };
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::env;
 
 extern crate serde;
 // #[macro_use]
 extern crate serde_json;
 
+pub use bulk::{
+    BulkFailure, BulkIngester, BulkIngesterBuilder, BulkIngesterStats, BulkItem, BulkMeta,
+    BulkOperation, BulkReport, BulkResponse, BulkUpdate,
+};
+pub use config::{ClientBuilder, ElasticConfig};
 pub use elastic_parser;
 pub use elastic_query_builder;
 pub use elasticsearch::http::Url;
 use elasticsearch::ilm::IlmPutLifecycleParts;
+use elasticsearch::nodes::NodesInfoParts;
 use elasticsearch::params::Refresh;
+pub use mapping::{ElasticMapping, FieldMapping, MappingDiff, MappingMismatch};
+pub use paging::{merge_slices, HitStream, PitGuard};
+use pool::sniffed_urls;
+pub use pool::{MultiNodeConnectionPool, PoolSettings};
+pub use repository::{Document, Repository};
+use retry::is_connection_error;
+pub use retry::RetryPolicy;
+use std::future::Future;
+use std::sync::Arc;
+pub use tls::TlsConfig;
+pub use transfer::{ExportOptions, ImportFailure, ImportOptions, IndexHeader};
+pub use uiuifree_elastic_derive::ElasticMapping;
 
-pub fn el_client() -> Result<Elasticsearch, ElasticError> {
-    dotenv().ok();
-    let host = env::var("ELASTIC_HOST").unwrap_or("http://localhost:9200".to_string());
-    let transport = Transport::single_node(host.as_str());
-    // let transport = SingleNodeConnectionPool::new(Url::parse(host.as_str()).unwrap());
+/// Client from the `ELASTIC_*` environment, see [`ElasticConfig::from_env`].
+pub fn el_client() -> Result<ElasticClient, ElasticError> {
+    ClientBuilder::from_env()?.build_client()
+}
 
-    return match transport {
-        Ok(v) => Ok(Elasticsearch::new(v)),
-        Err(e) => Err(ElasticError::Connection(e.to_string())),
-    };
+/// Client for one node; a bad URL is an [`ElasticError::Config`].
+pub fn el_single_node(url: &str) -> Result<ElasticClient, ElasticError> {
+    ClientBuilder::new().node(url).build_client()
 }
 
-pub fn el_single_node(url: &str) -> Elasticsearch {
-    let pool = SingleNodeConnectionPool::new(Url::parse(url).unwrap());
-    Elasticsearch::new(TransportBuilder::new(pool).build().unwrap())
+fn connection_fault_url(error: &Error) -> Option<&Url> {
+    if !is_connection_error(error) {
+        return None;
+    }
+    std::error::Error::source(error)?
+        .downcast_ref::<reqwest::Error>()?
+        .url()
+}
+
+fn str_refs(values: &[String]) -> Vec<&str> {
+    values.iter().map(|v| v.as_str()).collect()
 }
 
 fn bool_to_refresh(value: bool) -> Refresh {
@@ -53,18 +88,75 @@ fn bool_to_refresh(value: bool) -> Refresh {
     }
 }
 
+fn ndjson_body(lines: &[Value]) -> Vec<JsonBody<&Value>> {
+    lines.iter().map(JsonBody::from).collect()
+}
+
+/// A bulk request can be repeated when every index and create action names
+/// its `_id` and there is no update or conditional write; otherwise a retry
+/// would add the documents again, apply an update, such as a counter
+/// increment, twice, or fail an applied write with a version conflict.
+fn bulk_is_idempotent(lines: &[Value]) -> bool {
+    let mut lines = lines.iter();
+    while let Some(action) = lines.next() {
+        let action = match action.as_object().and_then(|v| v.iter().next()) {
+            Some(v) => v,
+            None => return false,
+        };
+        if !action.1["if_seq_no"].is_null() || !action.1["version"].is_null() {
+            return false;
+        }
+        match action.0.as_str() {
+            "index" | "create" if action.1["_id"].is_null() => return false,
+            "update" => return false,
+            "delete" => continue,
+            _ => {}
+        }
+        lines.next();
+    }
+    true
+}
+
+fn transport_error(error: Error) -> ElasticError {
+    match is_connection_error(&error) {
+        true => ElasticError::Connection(error.to_string()),
+        false => ElasticError::Send(error.to_string()),
+    }
+}
+
+/// `Api` for an error body, `NotFound` for a bare 404, `Status` otherwise.
+async fn status_error(res: Response) -> ElasticError {
+    let status = res.status_code().as_u16();
+    let body = res.text().await.unwrap_or_default();
+    match ElasticError::from_status(status, body) {
+        ElasticError::Status(404, body) => ElasticError::NotFound(body),
+        e => e,
+    }
+}
+
+/// Every request goes through here: transport failures and non-2xx statuses
+/// become errors, so an `Ok` response always succeeded.
+async fn check_response(input: Result<Response, Error>) -> Result<Response, ElasticError> {
+    let input = input.map_err(transport_error)?;
+    if !input.status_code().is_success() {
+        return Err(status_error(input).await);
+    }
+    Ok(input)
+}
+
+/// For HEAD requests: 2xx is `true`, 404 is `false`.
+async fn exists_response(input: Result<Response, Error>) -> Result<bool, ElasticError> {
+    let input = input.map_err(transport_error)?;
+    if input.status_code().as_u16() == 404 {
+        return Ok(false);
+    }
+    check_response(Ok(input)).await.map(|_| true)
+}
+
 async fn parse_response<T: for<'de> serde::Deserialize<'de>>(
     input: Result<Response, Error>,
 ) -> Result<T, ElasticError> {
-    if input.is_err() {
-        return Err(ElasticError::Send(input.unwrap_err().to_string()));
-    }
-    let input = input.unwrap();
-    let status_code = input.status_code().as_u16();
-    if status_code != 200 {
-        let c = input.text().await;
-        return Err(ElasticError::Status(status_code, c.unwrap()));
-    }
+    let input = check_response(input).await?;
     let a = input.json::<Value>().await;
     if a.is_err() {
         return Err(ElasticError::JsonParse(a.err().unwrap().to_string()));
@@ -74,94 +166,481 @@ async fn parse_response<T: for<'de> serde::Deserialize<'de>>(
     if b.is_err() {
         return Err(ElasticError::JsonParse(v.to_string()));
     }
-    return Ok(b.unwrap());
+    Ok(b.unwrap())
 }
 
+/// Client handle. Cloning is cheap and every clone shares the same
+/// connections, so one client can be handed to spawned tasks or stored in
+/// application state.
+#[derive(Clone)]
 pub struct ElasticApi {
+    inner: Arc<ApiInner>,
+}
+
+#[derive(Clone)]
+struct ApiInner {
+    client: Elasticsearch,
+    pool: Option<MultiNodeConnectionPool>,
+    retry: RetryPolicy,
+}
+
+/// An [`Elasticsearch`] client with the connection pool and retry policy of
+/// the configuration it was built from, taken over by [`ElasticApi::new`].
+/// Derefs to the client and converts into it.
+#[derive(Debug, Clone)]
+pub struct ElasticClient {
     client: Elasticsearch,
+    pool: Option<MultiNodeConnectionPool>,
+    retry: Option<RetryPolicy>,
+}
+
+impl std::ops::Deref for ElasticClient {
+    type Target = Elasticsearch;
+
+    fn deref(&self) -> &Elasticsearch {
+        &self.client
+    }
+}
+
+impl From<Elasticsearch> for ElasticClient {
+    fn from(client: Elasticsearch) -> ElasticClient {
+        ElasticClient {
+            client,
+            pool: None,
+            retry: None,
+        }
+    }
+}
+
+impl From<ElasticClient> for Elasticsearch {
+    fn from(client: ElasticClient) -> Elasticsearch {
+        client.client
+    }
 }
 
 impl ElasticApi {
-    pub fn new(client: Elasticsearch) -> ElasticApi {
-        ElasticApi { client }
+    /// Takes an [`Elasticsearch`] client, or an [`ElasticClient`] from
+    /// [`el_client`] or [`ClientBuilder::build_client`] together with its
+    /// pool and retry policy.
+    pub fn new<C: Into<ElasticClient>>(client: C) -> ElasticApi {
+        let client = client.into();
+        ElasticApi {
+            inner: Arc::new(ApiInner {
+                client: client.client,
+                pool: client.pool,
+                retry: client.retry.unwrap_or_else(RetryPolicy::none),
+            }),
+        }
+    }
+    /// Retry policy for operations that are safe to repeat.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> ElasticApi {
+        Arc::make_mut(&mut self.inner).retry = policy;
+        self
+    }
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.inner.retry
+    }
+    /// Attaches the pool `client` was built with, so failing nodes are
+    /// reported back to it and sniffing can reseed it.
+    pub fn with_pool(mut self, pool: MultiNodeConnectionPool) -> ElasticApi {
+        Arc::make_mut(&mut self.inner).pool = Some(pool);
+        self
+    }
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+    pub fn from_env() -> Result<ElasticApi, ElasticError> {
+        ClientBuilder::from_env()?.build()
+    }
+    pub fn client(&self) -> &Elasticsearch {
+        &self.inner.client
+    }
+    pub fn pool(&self) -> Option<&MultiNodeConnectionPool> {
+        self.inner.pool.as_ref()
+    }
+
+    /// Fetches `_nodes/http` and reseeds the pool with the published addresses.
+    pub async fn sniff(&self) -> Result<Vec<Url>, ElasticError> {
+        let pool = match &self.inner.pool {
+            Some(v) => v,
+            None => {
+                return Err(ElasticError::Config(
+                    "sniffing requires a MultiNodeConnectionPool".to_string(),
+                ))
+            }
+        };
+        let res = self
+            .inner
+            .client
+            .nodes()
+            .info(NodesInfoParts::Metric(&["http"]))
+            .send()
+            .await;
+        let value: Value = parse_response(res).await?;
+        let urls = sniffed_urls(&value, &pool.scheme());
+        pool.reseed(urls.clone());
+        Ok(urls)
+    }
+
+    async fn sniff_if_due(&self, force: bool) {
+        if let Some(pool) = &self.inner.pool {
+            if pool.begin_sniff(force) {
+                let _ = self.sniff().await;
+                pool.finish_sniff();
+            }
+        }
+    }
+
+    /// Sends a request that is safe to repeat, retrying with the per-call
+    /// policy or else the client policy.
+    pub(crate) async fn send_idempotent<'a, F, Fut>(
+        &'a self,
+        retry: &Option<RetryPolicy>,
+        request: F,
+    ) -> Result<Response, Error>
+    where
+        F: Fn(&'a Elasticsearch) -> Fut,
+        Fut: Future<Output = Result<Response, Error>>,
+    {
+        self.send_retry(retry.as_ref().unwrap_or(&self.inner.retry), request)
+            .await
+    }
+
+    /// Sends a request that could duplicate work when repeated. Only a policy
+    /// given for this call retries it.
+    pub(crate) async fn send_non_idempotent<'a, F, Fut>(
+        &'a self,
+        retry: &Option<RetryPolicy>,
+        request: F,
+    ) -> Result<Response, Error>
+    where
+        F: Fn(&'a Elasticsearch) -> Fut,
+        Fut: Future<Output = Result<Response, Error>>,
+    {
+        match retry {
+            Some(policy) => self.send_retry(policy, request).await,
+            None => self.send(request).await,
+        }
+    }
+
+    async fn send_retry<'a, F, Fut>(
+        &'a self,
+        policy: &RetryPolicy,
+        request: F,
+    ) -> Result<Response, Error>
+    where
+        F: Fn(&'a Elasticsearch) -> Fut,
+        Fut: Future<Output = Result<Response, Error>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let res = self.send(&request).await;
+            if attempt >= policy.max_attempts || !policy.should_retry(&res) {
+                return res;
+            }
+            tokio::time::sleep(policy.delay(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    async fn send<'a, F, Fut>(&'a self, request: F) -> Result<Response, Error>
+    where
+        F: FnOnce(&'a Elasticsearch) -> Fut,
+        Fut: Future<Output = Result<Response, Error>>,
+    {
+        self.sniff_if_due(false).await;
+        let res = request(&self.inner.client).await;
+        if let Some(pool) = &self.inner.pool {
+            match &res {
+                Ok(v) => match v.status_code().as_u16() {
+                    502..=504 => pool.mark_dead(v.url()),
+                    _ => pool.mark_alive(v.url()),
+                },
+                Err(e) => {
+                    if let Some(url) = connection_fault_url(e) {
+                        pool.mark_dead(url);
+                        if pool.sniff_on_connection_fault() {
+                            self.sniff_if_due(true).await;
+                        }
+                    }
+                }
+            }
+        }
+        res
     }
     pub fn get(&self) -> GetApi {
-        GetApi::new(&self)
+        GetApi::new(self)
     }
     pub fn update(&self) -> UpdateApi {
-        UpdateApi::new(&self)
+        UpdateApi::new(self)
     }
     pub fn indices(&self) -> IndicesApi {
-        IndicesApi::new(&self)
+        IndicesApi::new(self)
     }
     pub fn search(&self) -> SearchApi {
-        SearchApi::new(&self)
+        SearchApi::new(self)
     }
     pub fn bulk(&self) -> BulkApi {
-        BulkApi::new(&self)
+        BulkApi::new(self)
     }
     pub fn index(&self) -> IndexApi {
-        IndexApi::new(&self)
+        IndexApi::new(self)
+    }
+    pub fn delete(&self) -> DeleteApi {
+        DeleteApi::new(self)
     }
     pub fn delete_by_query(&self) -> DeleteByQueryApi {
-        DeleteByQueryApi::new(&self)
+        DeleteByQueryApi::new(self)
     }
     pub fn update_by_query(&self) -> UpdateByQuery {
-        UpdateByQuery::new(&self)
+        UpdateByQuery::new(self)
     }
     pub fn ilm(&self) -> IlmApi {
-        IlmApi::new(&self)
+        IlmApi::new(self)
+    }
+    pub fn repository<T: Document>(&self) -> Repository<T> {
+        Repository::new(self)
     }
 }
 
-pub struct SearchApi<'a> {
-    api: &'a ElasticApi,
+pub struct SearchApi {
+    api: ElasticApi,
+    retry: Option<RetryPolicy>,
+    pit: Option<Value>,
 }
 
-impl SearchApi<'_> {
+impl SearchApi {
     pub fn new(api: &ElasticApi) -> SearchApi {
-        SearchApi { api }
+        SearchApi {
+            api: api.clone(),
+            retry: None,
+            pit: None,
+        }
+    }
+    /// Retry policy for this call, replacing the client policy.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+    /// Searches the point in time `id`, extending it by `keep_alive`. The
+    /// point in time decides the indices, so `search` ignores its `index`.
+    pub fn pit(mut self, id: &str, keep_alive: &str) -> Self {
+        self.pit = Some(json!({ "id": id, "keep_alive": keep_alive }));
+        self
     }
 }
 
-pub struct GetApi<'a> {
-    api: &'a ElasticApi,
+pub struct GetApi {
+    api: ElasticApi,
+    retry: Option<RetryPolicy>,
+    options: GetOptions,
 }
 
-impl GetApi<'_> {
+impl GetApi {
     pub fn new(api: &ElasticApi) -> GetApi {
-        GetApi { api }
+        GetApi {
+            api: api.clone(),
+            retry: None,
+            options: GetOptions::default(),
+        }
     }
+    /// Retry policy for this call, replacing the client policy.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+    /// Replaces every option set so far.
+    pub fn with_options(mut self, options: GetOptions) -> Self {
+        self.options = options;
+        self
+    }
+    /// Shard routing value the document was indexed with.
+    pub fn routing(mut self, routing: &str) -> Self {
+        self.options.routing = Some(routing.to_string());
+        self
+    }
+    /// Returns only these source fields, wildcards allowed.
+    pub fn source_includes(mut self, fields: &[&str]) -> Self {
+        self.options.source_includes = fields.iter().map(|v| v.to_string()).collect();
+        self
+    }
+    /// Leaves these source fields out, wildcards allowed.
+    pub fn source_excludes(mut self, fields: &[&str]) -> Self {
+        self.options.source_excludes = fields.iter().map(|v| v.to_string()).collect();
+        self
+    }
+}
+
+/// Options of [`GetApi`] reads.
+///
+/// ```no_run
+/// # async fn run(api: uiuifree_elastic::ElasticApi) {
+/// use uiuifree_elastic::GetOptions;
+///
+/// #[derive(serde::Deserialize)]
+/// struct Title {
+///     title: String,
+/// }
+///
+/// let options = GetOptions {
+///     source_includes: vec!["title".to_string()],
+///     realtime: Some(false),
+///     ..Default::default()
+/// };
+/// let title: Title = api.get().with_options(options).source("articles", "1").await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct GetOptions {
+    pub source_includes: Vec<String>,
+    pub source_excludes: Vec<String>,
+    /// Stored fields returned in [`GetResponse::fields`]; not used by
+    /// `source`.
+    pub stored_fields: Vec<String>,
+    pub routing: Option<String>,
+    /// Node or shard copy to read from, such as `_local`.
+    pub preference: Option<String>,
+    /// `false` reads only what the last refresh made searchable.
+    pub realtime: Option<bool>,
+    /// Refreshes the shard before reading.
+    pub refresh: Option<bool>,
+    /// Fails with a version conflict unless the document has this version.
+    pub version: Option<i64>,
 }
 
-pub struct DeleteApi<'a> {
-    api: &'a ElasticApi,
+/// Applies the URL options the get, source and exists requests share.
+macro_rules! get_options {
+    ($request:ident, $options:expr, $includes:expr, $excludes:expr) => {{
+        let options: &GetOptions = $options;
+        if let Some(v) = options.routing.as_deref() {
+            $request = $request.routing(v);
+        }
+        if !$includes.is_empty() {
+            $request = $request._source_includes($includes);
+        }
+        if !$excludes.is_empty() {
+            $request = $request._source_excludes($excludes);
+        }
+        if let Some(v) = options.preference.as_deref() {
+            $request = $request.preference(v);
+        }
+        if let Some(v) = options.realtime {
+            $request = $request.realtime(v);
+        }
+        if let Some(v) = options.refresh {
+            $request = $request.refresh(v);
+        }
+        if let Some(v) = options.version {
+            $request = $request.version(v);
+        }
+    }};
+}
+
+pub struct DeleteApi {
+    api: ElasticApi,
+    retry: Option<RetryPolicy>,
+    routing: Option<String>,
+    refresh: bool,
+    if_seq_no: Option<(i64, i64)>,
 }
 
-impl DeleteApi<'_> {
+impl DeleteApi {
     pub fn new(api: &ElasticApi) -> DeleteApi {
-        DeleteApi { api }
+        DeleteApi {
+            api: api.clone(),
+            retry: None,
+            routing: None,
+            refresh: false,
+            if_seq_no: None,
+        }
+    }
+    /// Retry policy for this call, replacing the client policy.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+    /// Shard routing value the document was indexed with.
+    pub fn routing(mut self, routing: &str) -> Self {
+        self.routing = Some(routing.to_string());
+        self
+    }
+    /// Refreshes the shard so the deletion is visible to searches.
+    pub fn refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+    /// Deletes only if the document was last written at this sequence
+    /// number and primary term; otherwise fails with a version conflict.
+    pub fn if_seq_no(mut self, seq_no: i64, primary_term: i64) -> Self {
+        self.if_seq_no = Some((seq_no, primary_term));
+        self
     }
 }
 
-pub struct DeleteDocResponse {}
+/// Response of [`DeleteApi::doc`].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeleteDocResponse {
+    pub _index: String,
+    pub _id: String,
+    pub _version: Option<u64>,
+    pub _seq_no: Option<u64>,
+    pub _primary_term: Option<u64>,
+    /// `deleted` or `not_found`.
+    pub result: String,
+    pub _shards: Option<Shards>,
+}
 
-impl DeleteApi<'_> {
-    pub async fn doc(&self, index: &str, id: &str) -> Result<Response, ElasticError> {
+impl DeleteDocResponse {
+    pub fn is_deleted(&self) -> bool {
+        self.result == "deleted"
+    }
+    pub fn is_not_found(&self) -> bool {
+        self.result == "not_found"
+    }
+}
+
+impl DeleteApi {
+    /// A missing document is a `not_found` result rather than an error; a
+    /// missing index is still an error.
+    ///
+    /// Only a `with_retry` policy retries a delete: repeating one that was
+    /// applied before a timeout answers `not_found`, or a version conflict
+    /// with [`if_seq_no`](DeleteApi::if_seq_no).
+    pub async fn doc(&self, index: &str, id: &str) -> Result<DeleteDocResponse, ElasticError> {
+        let routing = self.routing.as_deref();
+        let refresh = self.refresh;
+        let if_seq_no = self.if_seq_no;
         let res = self
             .api
-            .client
-            .delete(DeleteParts::IndexId(index, id))
-            .send()
+            .send_non_idempotent(&self.retry, |client| async move {
+                let mut request = client
+                    .delete(DeleteParts::IndexId(index, id))
+                    .refresh(bool_to_refresh(refresh));
+                if let Some(routing) = routing {
+                    request = request.routing(routing);
+                }
+                if let Some((seq_no, primary_term)) = if_seq_no {
+                    request = request.if_seq_no(seq_no).if_primary_term(primary_term);
+                }
+                request.send().await
+            })
             .await;
-        if res.is_err() {
-            return Err(ElasticError::Response(res.err().unwrap().to_string()));
+        let res = res.map_err(transport_error)?;
+        if res.status_code().as_u16() == 404 {
+            let body = res.text().await.unwrap_or_default();
+            return match serde_json::from_str::<DeleteDocResponse>(&body) {
+                Ok(v) if v.is_not_found() => Ok(v),
+                _ => Err(match ElasticError::from_status(404, body) {
+                    ElasticError::Status(_, body) => ElasticError::NotFound(body),
+                    e => e,
+                }),
+            };
         }
-        return Ok(res.unwrap());
+        parse_response(Ok(res)).await
     }
 }
 
-impl SearchApi<'_> {
+impl SearchApi {
     pub async fn search<T>(
         &self,
         index: &[&str],
@@ -170,38 +649,137 @@ impl SearchApi<'_> {
     where
         T: DeserializeOwned + 'static + Clone,
     {
+        if let Some(pit) = &self.pit {
+            let mut body = query_builder.build();
+            body["pit"] = pit.clone();
+            let body = &body;
+            let res = self
+                .api
+                .send_idempotent(&self.retry, |client| async move {
+                    client
+                        .search(SearchParts::None)
+                        .body(body)
+                        .from(query_builder.get_from())
+                        .size(query_builder.get_size())
+                        .send()
+                        .await
+                })
+                .await;
+            return parse_response(res).await.map(Some);
+        }
         if !query_builder.get_scroll().is_empty() {
-            return match self
+            let res = self
                 .api
-                .client
-                .search(SearchParts::Index(index))
-                .body(query_builder.build())
-                .from(query_builder.get_from())
-                .size(query_builder.get_size())
-                .scroll(query_builder.get_scroll())
-                .send()
-                .await
-            {
-                Ok(response) => {
-                    return match response.json::<SearchResponse<T>>().await {
-                        Ok(v) => Ok(Some(v)),
-                        Err(v) => {
-                            return Err(ElasticError::JsonParse(v.to_string()));
-                        }
-                    };
-                }
-                Err(_) => Ok(None),
-            };
+                .send_idempotent(&self.retry, |client| async move {
+                    client
+                        .search(SearchParts::Index(index))
+                        .body(query_builder.build())
+                        .from(query_builder.get_from())
+                        .size(query_builder.get_size())
+                        .scroll(query_builder.get_scroll())
+                        .send()
+                        .await
+                })
+                .await;
+            return parse_response(res).await.map(Some);
         }
 
         let res = self
             .api
-            .client
-            .search(SearchParts::Index(&index))
-            .body(query_builder.build())
-            .from(query_builder.get_from())
-            .size(query_builder.get_size())
-            .send()
+            .send_idempotent(&self.retry, |client| async move {
+                client
+                    .search(SearchParts::Index(index))
+                    .body(query_builder.build())
+                    .from(query_builder.get_from())
+                    .size(query_builder.get_size())
+                    .send()
+                    .await
+            })
+            .await;
+        parse_response(res).await.map(Some)
+    }
+    /// Number of documents matching the query of `query_builder`. Unlike a
+    /// search total, the count is exact past 10,000.
+    pub async fn count(
+        &self,
+        index: &[&str],
+        query_builder: &QueryBuilder,
+    ) -> Result<CountResponse, ElasticError> {
+        self.count_with(index, query_builder, &CountOptions::default())
+            .await
+    }
+    pub async fn count_with(
+        &self,
+        index: &[&str],
+        query_builder: &QueryBuilder,
+        options: &CountOptions,
+    ) -> Result<CountResponse, ElasticError> {
+        // The count API takes only the query.
+        let body = &json!({ "query": query_builder.build()["query"] });
+        let res = self
+            .api
+            .send_idempotent(&self.retry, |client| async move {
+                let parts = match index.is_empty() {
+                    true => CountParts::None,
+                    false => CountParts::Index(index),
+                };
+                let mut request = client.count(parts).body(body);
+                if let Some(v) = options.ignore_unavailable {
+                    request = request.ignore_unavailable(v);
+                }
+                if let Some(v) = options.allow_no_indices {
+                    request = request.allow_no_indices(v);
+                }
+                request.send().await
+            })
+            .await;
+        parse_response(res).await
+    }
+    /// Sends every `(index, query)` pair in one `_msearch` request. A failed
+    /// search is reported in its own slot of the response, the others still
+    /// succeed.
+    ///
+    /// ```no_run
+    /// # async fn run(api: uiuifree_elastic::ElasticApi) {
+    /// use serde_json::Value;
+    /// use uiuifree_elastic::elastic_query_builder::QueryBuilder;
+    ///
+    /// let (results, facets) = (QueryBuilder::new(), QueryBuilder::new());
+    /// let res = api
+    ///     .search()
+    ///     .msearch(&[(&["products"], &results), (&["facets"], &facets)])
+    ///     .await
+    ///     .unwrap();
+    /// let products = res.get::<Value>(0);
+    /// let facets = res.get::<Value>(1);
+    /// # }
+    /// ```
+    pub async fn msearch(
+        &self,
+        searches: &[(&[&str], &QueryBuilder)],
+    ) -> Result<MultiSearchResponse, ElasticError> {
+        let mut lines = Vec::with_capacity(searches.len() * 2);
+        for (index, query_builder) in searches {
+            lines.push(match index.is_empty() {
+                true => json!({}),
+                false => json!({ "index": index }),
+            });
+            // `build` leaves paging to the URL, which msearch does not have.
+            let mut body = query_builder.build();
+            body["from"] = json!(query_builder.get_from());
+            body["size"] = json!(query_builder.get_size());
+            lines.push(body);
+        }
+        let lines = lines.as_slice();
+        let res = self
+            .api
+            .send_idempotent(&self.retry, |client| async move {
+                client
+                    .msearch(MsearchParts::None)
+                    .body(ndjson_body(lines))
+                    .send()
+                    .await
+            })
             .await;
         parse_response(res).await
     }
@@ -213,24 +791,18 @@ impl SearchApi<'_> {
     where
         T: DeserializeOwned + 'static + Clone,
     {
-        match self
+        // Each call moves the scroll cursor, so a repeat would skip a page.
+        let res = self
             .api
-            .client
-            .scroll(ScrollParts::ScrollId(scroll_id))
-            .scroll(alive)
-            .send()
-            .await
-        {
-            Ok(response) => {
-                return match response.json::<SearchResponse<T>>().await {
-                    Ok(v) => Ok(Some(v)),
-                    Err(v) => {
-                        return Err(ElasticError::Response(v.to_string()));
-                    }
-                };
-            }
-            Err(_) => Ok(None),
-        }
+            .send_non_idempotent(&self.retry, |client| async move {
+                client
+                    .scroll(ScrollParts::ScrollId(scroll_id))
+                    .scroll(alive)
+                    .send()
+                    .await
+            })
+            .await;
+        parse_response(res).await.map(Some)
     }
 
     pub async fn first_search<T>(
@@ -241,229 +813,321 @@ impl SearchApi<'_> {
     where
         T: DeserializeOwned + 'static + Clone,
     {
-        return match self
+        let body = &query_builder.build();
+        let res = self
             .api
-            .client
-            .search(SearchParts::Index(&[index]))
-            .body(query_builder.build())
-            .size(1)
-            .send()
-            .await
-        {
-            Ok(response) => {
-                return match response.json::<SearchResponse<T>>().await {
-                    Ok(v) => {
-                        let hits = v.hits;
-                        if hits.is_none() {
-                            return Ok(None);
-                        }
-                        let hits = hits.unwrap();
-                        let value = hits.hits;
-                        if value.is_none() {
-                            return Ok(None);
-                        }
-                        let value = value.unwrap().pop();
-                        if value.is_none() {
-                            return Ok(None);
-                        }
-                        Ok(Some(value.unwrap()))
-                    }
-                    Err(v) => {
-                        return Err(ElasticError::Response(v.to_string()));
-                    }
-                };
-            }
-            Err(_) => Ok(None),
-        };
+            .send_idempotent(&self.retry, |client| async move {
+                client
+                    .search(SearchParts::Index(&[index]))
+                    .body(body)
+                    .size(1)
+                    .send()
+                    .await
+            })
+            .await;
+        let res: SearchResponse<T> = parse_response(res).await?;
+        Ok(res.hits.and_then(|v| v.hits).and_then(|mut v| v.pop()))
     }
 }
 
-pub struct IndicesApi<'a> {
-    api: &'a ElasticApi,
+pub struct IndicesApi {
+    api: ElasticApi,
+    retry: Option<RetryPolicy>,
 }
 
-impl IndicesApi<'_> {
+impl IndicesApi {
     pub fn new(api: &ElasticApi) -> IndicesApi {
-        IndicesApi { api }
+        IndicesApi {
+            api: api.clone(),
+            retry: None,
+        }
+    }
+    /// Retry policy for this call, replacing the client policy.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
     }
 }
 
-impl IndicesApi<'_> {
+impl IndicesApi {
     pub async fn get_alias(&self, index: &[&str]) -> Result<Value, ElasticError> {
         let res = self
             .api
-            .client
-            .indices()
-            .get_alias(IndicesGetAliasParts::Index(index))
-            .send()
+            .send_idempotent(&self.retry, |client| async move {
+                client
+                    .indices()
+                    .get_alias(IndicesGetAliasParts::Index(index))
+                    .send()
+                    .await
+            })
             .await;
-
-        match res {
-            Ok(v) => {
-                if v.status_code() != 200 {
-                    return Err(ElasticError::NotFound(index.join(",")));
-                }
-                Ok(v.json().await.unwrap())
-            }
-            Err(err) => Err(ElasticError::Connection(err.to_string())),
-        }
+        parse_response(res).await
     }
     pub async fn exist_alias(&self, index: &[&str]) -> Result<(), ElasticError> {
         let res = self
             .api
-            .client
-            .indices()
-            .exists_alias(IndicesExistsAliasParts::Name(index))
-            .send()
+            .send_idempotent(&self.retry, |client| async move {
+                client
+                    .indices()
+                    .exists_alias(IndicesExistsAliasParts::Name(index))
+                    .send()
+                    .await
+            })
             .await;
-
-        match res {
-            Ok(v) => {
-                if v.status_code() != 200 {
-                    return Err(ElasticError::NotFound(index.join(",")));
-                }
-                Ok(())
-            }
-            Err(err) => Err(ElasticError::Connection(err.to_string())),
+        match exists_response(res).await? {
+            true => Ok(()),
+            false => Err(ElasticError::NotFound(index.join(","))),
         }
     }
     pub async fn update_alias(&self, value: Value) -> Result<Value, ElasticError> {
+        let value = &value;
         let res = self
             .api
-            .client
-            .indices()
-            .update_aliases()
-            .body(value)
-            .send()
+            .send_non_idempotent(&self.retry, |client| async move {
+                client.indices().update_aliases().body(value).send().await
+            })
             .await;
-
-        match res {
-            Ok(v) => {
-                if v.status_code() != 200 {
-                    return Err(ElasticError::Status(
-                        v.status_code().as_u16(),
-                        v.text().await.unwrap_or_default(),
-                    ));
-                }
-                Ok(v.json().await.unwrap())
-            }
-            Err(err) => Err(ElasticError::Connection(err.to_string())),
-        }
+        parse_response(res).await
     }
-    pub async fn exists(&self, index: &str) -> Result<(), ElasticError> {
+    /// `false` for a missing index; failed requests are errors.
+    pub async fn exists(&self, index: &str) -> Result<bool, ElasticError> {
         let res = self
             .api
-            .client
-            .indices()
-            .exists(IndicesExistsParts::Index(&[index]))
-            .send()
+            .send_idempotent(&self.retry, |client| async move {
+                client
+                    .indices()
+                    .exists(IndicesExistsParts::Index(&[index]))
+                    .send()
+                    .await
+            })
             .await;
-        // el_client()?.index(IndexParts::IndexId("1","1")).body()
-
-        if res.is_err() {
-            return Err(ElasticError::Connection(res.unwrap_err().to_string()));
-        }
-        if res.unwrap().status_code() != 200 {
-            return Err(ElasticError::NotFound(index.to_string()));
-        }
-        Ok(())
+        exists_response(res).await
     }
     pub async fn refresh(&self, index: &str) -> Result<IndicesRefreshResponse, ElasticError> {
         let res = self
             .api
-            .client
-            .indices()
-            .refresh(IndicesRefreshParts::Index(&[index]))
-            .send()
+            .send_idempotent(&self.retry, |client| async move {
+                client
+                    .indices()
+                    .refresh(IndicesRefreshParts::Index(&[index]))
+                    .send()
+                    .await
+            })
             .await;
-        if res.is_err() {
-            return Err(ElasticError::Connection(res.err().unwrap().to_string()));
-        }
-
         parse_response(res).await
     }
     pub async fn create<T>(&self, index: &str, json: T) -> Result<bool, ElasticError>
     where
         T: Serialize,
     {
-        return match self
+        let json = &json;
+        let res = self
             .api
-            .client
-            .indices()
-            .create(IndicesCreateParts::Index(index))
-            .body(json)
-            .send()
+            .send_non_idempotent(&self.retry, |client| async move {
+                client
+                    .indices()
+                    .create(IndicesCreateParts::Index(index))
+                    .body(json)
+                    .send()
+                    .await
+            })
+            .await;
+        parse_response::<Acknowledged>(res)
             .await
-        {
-            Ok(v) => {
-                let s = v.status_code();
-                Ok(s == 200)
-            }
-            Err(e) => Err(ElasticError::Send(e.to_string())),
-        };
+            .map(|v| v.acknowledged)
     }
     pub async fn put_index_template<T>(&self, index: &str, json: T) -> Result<bool, ElasticError>
     where
         T: Serialize,
     {
-        return match self
+        let json = &json;
+        let res = self
             .api
-            .client
-            .indices()
-            .put_index_template(IndicesPutIndexTemplateParts::Name(index))
-            .body(json)
-            .send()
+            .send_idempotent(&self.retry, |client| async move {
+                client
+                    .indices()
+                    .put_index_template(IndicesPutIndexTemplateParts::Name(index))
+                    .body(json)
+                    .send()
+                    .await
+            })
+            .await;
+        parse_response::<Acknowledged>(res)
             .await
-        {
-            Ok(v) => {
-                let s = v.status_code();
-                Ok(s == 200)
-            }
-            Err(e) => Err(ElasticError::Send(e.to_string())),
-        };
+            .map(|v| v.acknowledged)
     }
-    pub async fn exists_index_template<T>(&self, index: &str) -> Result<bool, ElasticError>
-    {
-        return match self
+    pub async fn exists_index_template<T>(&self, index: &str) -> Result<bool, ElasticError> {
+        let res = self
             .api
-            .client
-            .indices()
-            .exists_index_template(IndicesExistsIndexTemplateParts::Name(index))
-            .send()
-            .await
-        {
-            Ok(v) => {
-                let s = v.status_code();
-                Ok(s == 200)
-            }
-            Err(e) => Err(ElasticError::Send(e.to_string())),
-        };
+            .send_idempotent(&self.retry, |client| async move {
+                client
+                    .indices()
+                    .exists_index_template(IndicesExistsIndexTemplateParts::Name(index))
+                    .send()
+                    .await
+            })
+            .await;
+        exists_response(res).await
     }
 
     pub async fn delete(&self, index: &str) -> Result<bool, ElasticError> {
         let res = self
             .api
-            .client
-            .indices()
-            .delete(IndicesDeleteParts::Index(&[index]))
-            .send()
+            .send_non_idempotent(&self.retry, |client| async move {
+                client
+                    .indices()
+                    .delete(IndicesDeleteParts::Index(&[index]))
+                    .send()
+                    .await
+            })
             .await;
-        if res.is_err() {
-            return Err(ElasticError::Response(res.err().unwrap().to_string()));
-        }
-        let res = res.unwrap();
-        Ok(res.status_code() == 200)
+        parse_response::<Acknowledged>(res)
+            .await
+            .map(|v| v.acknowledged)
     }
 
     pub async fn recreate<T>(&self, index: &str, json: T) -> Result<bool, ElasticError>
     where
         T: Serialize,
     {
-        if self.api.indices().exists(index).await.is_ok() {
+        if self.api.indices().exists(index).await? {
             let _ = self.api.indices().delete(index).await?;
         }
-        Ok(self.api.indices().create(index, json).await?)
+        self.api.indices().create(index, json).await
     }
+
+    /// `_mapping` of `index`, keyed by concrete index name.
+    pub async fn get_mapping(&self, index: &str) -> Result<Value, ElasticError> {
+        let res = self
+            .api
+            .send_idempotent(&self.retry, |client| async move {
+                client
+                    .indices()
+                    .get_mapping(IndicesGetMappingParts::Index(&[index]))
+                    .send()
+                    .await
+            })
+            .await;
+        parse_response(res).await
+    }
+
+    /// Compares the live mapping of `index` with `expected`, a `mappings`
+    /// object or a create index body. For an alias or pattern the differences
+    /// of every concrete index are merged.
+    pub async fn validate_mapping(
+        &self,
+        index: &str,
+        expected: &Value,
+    ) -> Result<MappingDiff, ElasticError> {
+        let live = self.get_mapping(index).await?;
+        let mut diff = MappingDiff::default();
+        if let Some(indices) = live.as_object() {
+            for v in indices.values() {
+                diff.merge(MappingDiff::compare(expected, &v["mappings"]));
+            }
+        }
+        Ok(diff)
+    }
+
+    /// [`validate_mapping`](IndicesApi::validate_mapping) against the mapping
+    /// derived for `T`.
+    pub async fn validate_mapping_for<T: ElasticMapping>(
+        &self,
+        index: &str,
+    ) -> Result<MappingDiff, ElasticError> {
+        self.validate_mapping(index, &T::mapping()).await
+    }
+
+    /// Creates `index` with the mapping derived for `T`.
+    pub async fn create_from<T: ElasticMapping>(&self, index: &str) -> Result<bool, ElasticError> {
+        self.create(index, T::index_body()).await
+    }
+
+    /// Deletes `index` if it exists and creates it with the mapping derived
+    /// for `T`.
+    pub async fn recreate_from<T: ElasticMapping>(
+        &self,
+        index: &str,
+    ) -> Result<bool, ElasticError> {
+        self.recreate(index, T::index_body()).await
+    }
+}
+
+/// Response of [`GetApi::doc_response`].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GetResponse<T> {
+    pub _index: String,
+    pub _id: String,
+    pub _version: Option<u64>,
+    pub _seq_no: Option<u64>,
+    pub _primary_term: Option<u64>,
+    pub _routing: Option<String>,
+    #[serde(default)]
+    pub found: bool,
+    pub _source: Option<T>,
+    /// Stored fields asked for with [`GetOptions::stored_fields`].
+    pub fields: Option<Value>,
+}
+
+/// Response of [`SearchApi::msearch`], one entry per search in request
+/// order. Each entry is read with its own source type.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MultiSearchResponse {
+    pub took: Option<usize>,
+    pub responses: Vec<Value>,
+}
+
+impl MultiSearchResponse {
+    pub fn len(&self) -> usize {
+        self.responses.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.responses.is_empty()
+    }
+    /// The `i`th search, or the error it failed with.
+    pub fn get<T>(&self, i: usize) -> Result<SearchResponse<T>, ElasticError>
+    where
+        T: DeserializeOwned + Clone,
+    {
+        let response = match self.responses.get(i) {
+            Some(v) => v,
+            None => {
+                return Err(ElasticError::Response(format!(
+                    "msearch has {} responses, no response {}",
+                    self.responses.len(),
+                    i
+                )))
+            }
+        };
+        if response.get("error").is_some() {
+            let status = response["status"].as_u64().unwrap_or(500) as u16;
+            return Err(ElasticError::from_status(status, response.to_string()));
+        }
+        serde_json::from_value(response.clone())
+            .map_err(|_| ElasticError::JsonParse(response.to_string()))
+    }
+    /// Every search with the same source type.
+    pub fn results<T>(&self) -> Vec<Result<SearchResponse<T>, ElasticError>>
+    where
+        T: DeserializeOwned + Clone,
+    {
+        (0..self.responses.len()).map(|i| self.get(i)).collect()
+    }
+}
+
+/// Index options of [`SearchApi::count_with`].
+#[derive(Debug, Clone, Default)]
+pub struct CountOptions {
+    /// Skips missing or closed indices instead of failing.
+    pub ignore_unavailable: Option<bool>,
+    /// Whether wildcards and aliases that match no index are an error.
+    pub allow_no_indices: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CountResponse {
+    pub count: u64,
+    pub _shards: Option<Shards>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -471,17 +1135,23 @@ pub struct IndicesRefreshResponse {
     pub _shards: Option<Shards>,
 }
 
-impl GetApi<'_> {
+impl GetApi {
     pub async fn source<T: for<'de> serde::Deserialize<'de>>(
         &self,
         index: &str,
         id: &str,
     ) -> Result<T, ElasticError> {
+        let options = &self.options;
+        let includes = str_refs(&options.source_includes);
+        let excludes = str_refs(&options.source_excludes);
+        let (includes, excludes) = (includes.as_slice(), excludes.as_slice());
         let res = self
             .api
-            .client
-            .get_source(GetSourceParts::IndexId(index, id))
-            .send()
+            .send_idempotent(&self.retry, |client| async move {
+                let mut request = client.get_source(GetSourceParts::IndexId(index, id));
+                get_options!(request, options, includes, excludes);
+                request.send().await
+            })
             .await;
         parse_response(res).await
     }
@@ -490,28 +1160,192 @@ impl GetApi<'_> {
         index: &str,
         id: &str,
     ) -> Result<Doc<T>, ElasticError> {
+        let res = self.send_get(index, id).await;
+        parse_response::<Doc<T>>(res).await
+    }
+
+    /// The document with its metadata and the stored fields asked for. A
+    /// missing document is a `NotFound` error.
+    pub async fn doc_response<T: DeserializeOwned>(
+        &self,
+        index: &str,
+        id: &str,
+    ) -> Result<GetResponse<T>, ElasticError> {
+        let res = self.send_get(index, id).await;
+        parse_response(res).await
+    }
+
+    async fn send_get(&self, index: &str, id: &str) -> Result<Response, Error> {
+        let options = &self.options;
+        let includes = str_refs(&options.source_includes);
+        let excludes = str_refs(&options.source_excludes);
+        let stored_fields = str_refs(&options.stored_fields);
+        let (includes, excludes) = (includes.as_slice(), excludes.as_slice());
+        let stored_fields = stored_fields.as_slice();
+        self.api
+            .send_idempotent(&self.retry, |client| async move {
+                let mut request = client.get(GetParts::IndexId(index, id));
+                get_options!(request, options, includes, excludes);
+                if !stored_fields.is_empty() {
+                    request = request.stored_fields(stored_fields);
+                }
+                request.send().await
+            })
+            .await
+    }
+
+    /// Whether the document exists, without fetching it.
+    pub async fn exists(&self, index: &str, id: &str) -> Result<bool, ElasticError> {
+        let options = &self.options;
+        // Nothing is fetched, so source filters do not apply.
+        let no_source: &[&str] = &[];
         let res = self
             .api
-            .client
-            .get(GetParts::IndexId(index, id))
-            .send()
+            .send_idempotent(&self.retry, |client| async move {
+                let mut request = client.exists(ExistsParts::IndexId(index, id));
+                get_options!(request, options, no_source, no_source);
+                request.send().await
+            })
             .await;
+        exists_response(res).await
+    }
 
-        parse_response::<Doc<T>>(res).await
+    /// Sources of `ids` in one `_mget` request, in the order of `ids`, with
+    /// `None` for missing documents.
+    pub async fn many<T: DeserializeOwned>(
+        &self,
+        index: &str,
+        ids: &[&str],
+    ) -> Result<Vec<Option<T>>, ElasticError> {
+        let docs: Vec<MultiGetDoc> = ids.iter().map(|v| MultiGetDoc::new(v)).collect();
+        self.many_docs(index, &docs).await
+    }
+
+    /// [`many`](GetApi::many) with an index or routing per document. `index`
+    /// is used for documents that name none.
+    pub async fn many_docs<T: DeserializeOwned>(
+        &self,
+        index: &str,
+        docs: &[MultiGetDoc],
+    ) -> Result<Vec<Option<T>>, ElasticError> {
+        if docs.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut source = json!({});
+        let options = &self.options;
+        if !options.source_includes.is_empty() {
+            source["includes"] = json!(options.source_includes);
+        }
+        if !options.source_excludes.is_empty() {
+            source["excludes"] = json!(options.source_excludes);
+        }
+        let docs: Vec<Value> = docs
+            .iter()
+            .map(|doc| {
+                let mut v = json!({
+                    "_index": doc.index.as_deref().unwrap_or(index),
+                    "_id": doc.id,
+                });
+                if let Some(routing) = doc.routing.as_ref().or(options.routing.as_ref()) {
+                    v["routing"] = json!(routing);
+                }
+                if source != json!({}) {
+                    v["_source"] = source.clone();
+                }
+                if !options.stored_fields.is_empty() {
+                    v["stored_fields"] = json!(options.stored_fields);
+                }
+                v
+            })
+            .collect();
+        let body = &json!({ "docs": docs });
+        let res = self
+            .api
+            .send_idempotent(&self.retry, |client| async move {
+                let mut request = client.mget(MgetParts::None).body(body);
+                if let Some(v) = options.preference.as_deref() {
+                    request = request.preference(v);
+                }
+                if let Some(v) = options.realtime {
+                    request = request.realtime(v);
+                }
+                if let Some(v) = options.refresh {
+                    request = request.refresh(v);
+                }
+                request.send().await
+            })
+            .await;
+        let res: Value = parse_response(res).await?;
+        let found = res["docs"].as_array().cloned().unwrap_or_default();
+        if found.len() != docs.len() {
+            return Err(ElasticError::Response(res.to_string()));
+        }
+        found
+            .into_iter()
+            .map(|doc| {
+                if let Some(error) = doc.get("error") {
+                    // Failed documents carry no status of their own.
+                    return Err(match ErrorCause::from_value(error) {
+                        Some(v) => ElasticError::Item(Box::new(v)),
+                        None => ElasticError::Response(doc.to_string()),
+                    });
+                }
+                match doc["found"].as_bool() {
+                    Some(true) => serde_json::from_value(doc["_source"].clone())
+                        .map(Some)
+                        .map_err(|_| ElasticError::JsonParse(doc.to_string())),
+                    _ => Ok(None),
+                }
+            })
+            .collect()
+    }
+}
+
+/// A document of [`GetApi::many_docs`].
+#[derive(Debug, Clone, Default)]
+pub struct MultiGetDoc {
+    pub id: String,
+    pub index: Option<String>,
+    pub routing: Option<String>,
+}
+
+impl MultiGetDoc {
+    pub fn new(id: &str) -> MultiGetDoc {
+        MultiGetDoc {
+            id: id.to_string(),
+            ..Default::default()
+        }
+    }
+    pub fn index(mut self, index: &str) -> Self {
+        self.index = Some(index.to_string());
+        self
+    }
+    pub fn routing(mut self, routing: &str) -> Self {
+        self.routing = Some(routing.to_string());
+        self
     }
 }
 
-pub struct UpdateApi<'a> {
-    api: &'a ElasticApi,
+pub struct UpdateApi {
+    api: ElasticApi,
+    retry: Option<RetryPolicy>,
 }
 
-impl UpdateApi<'_> {
+impl UpdateApi {
     pub fn new(api: &ElasticApi) -> UpdateApi {
-        UpdateApi { api }
+        UpdateApi {
+            api: api.clone(),
+            retry: None,
+        }
+    }
+    /// Retry policy for this call, replacing the client policy.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
     }
 }
 
-impl UpdateApi<'_> {
+impl UpdateApi {
     pub async fn doc<T: serde::Serialize>(
         &self,
         index: &str,
@@ -519,88 +1353,109 @@ impl UpdateApi<'_> {
         source: T,
         refresh: bool,
     ) -> Result<(), ElasticError> {
+        let body = &json!({ "doc": source });
         let res = self
             .api
-            .client
-            .update(UpdateParts::IndexId(index, id))
-            .refresh(bool_to_refresh(refresh))
-            .body(json!({ "doc": source }))
-            .send()
+            .send_idempotent(&self.retry, |client| async move {
+                client
+                    .update(UpdateParts::IndexId(index, id))
+                    .refresh(bool_to_refresh(refresh))
+                    .body(body)
+                    .send()
+                    .await
+            })
             .await;
-        if res.is_err() {
-            return Err(ElasticError::Response(res.unwrap_err().to_string()));
-        }
-        let code = res.as_ref().unwrap().status_code();
-        if code == 404 {
-            return Err(ElasticError::NotFound(format!("not found entity: {}", id)));
-        }
-        let res = res.unwrap();
-        if res.status_code() != 200 {
-            return Err(ElasticError::Response(res.text().await.unwrap_or_default()));
-        }
+        check_response(res).await?;
         Ok(())
     }
 }
 
-pub struct BulkApi<'a> {
-    api: &'a ElasticApi,
+pub struct BulkApi {
+    api: ElasticApi,
+    retry: Option<RetryPolicy>,
+    fail_on_item_errors: bool,
 }
 
-impl BulkApi<'_> {
+impl BulkApi {
     pub fn new(api: &ElasticApi) -> BulkApi {
-        BulkApi { api }
+        BulkApi {
+            api: api.clone(),
+            retry: None,
+            fail_on_item_errors: false,
+        }
+    }
+    /// Retry policy for this call, replacing the client policy.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+    /// Returns [`ElasticError::BulkPartialFailure`] instead of the response
+    /// when any item failed.
+    pub fn fail_on_item_errors(mut self, fail: bool) -> Self {
+        self.fail_on_item_errors = fail;
+        self
     }
-}
 
-impl BulkApi<'_> {
-    pub async fn bulk<T: serde::Serialize>(
+    async fn finish(
         &self,
-        sources: Vec<T>,
-        refresh: bool,
-    ) -> Result<Value, ElasticError> {
-        let mut body: Vec<JsonBody<_>> = Vec::with_capacity(4);
-        for source in sources {
-            body.push(json!(source).into())
+        res: Result<Response, elasticsearch::Error>,
+        lines: &[Value],
+    ) -> Result<BulkResponse, ElasticError> {
+        let res: BulkResponse = parse_response(res).await?;
+        if self.fail_on_item_errors && res.errors {
+            let failures = res.failures(lines);
+            if !failures.is_empty() {
+                return Err(ElasticError::BulkPartialFailure(failures));
+            }
         }
+        Ok(res)
+    }
+}
 
-        parse_response(
-            self.api
-                .client
+impl BulkApi {
+    pub async fn bulk<'a, T: serde::Serialize>(
+        &'a self,
+        sources: Vec<T>,
+        refresh: bool,
+    ) -> Result<BulkResponse, ElasticError> {
+        let lines: Vec<Value> = sources.into_iter().map(|v| json!(v)).collect();
+        let lines = &lines;
+        let request = |client: &'a Elasticsearch| async move {
+            client
                 .bulk(BulkParts::None)
-                .body(body)
+                .body(ndjson_body(lines))
                 .refresh(bool_to_refresh(refresh))
                 .send()
-                .await,
-        )
-            .await
-        // let res = client.bulk(BulkParts::None).body(body).send().await;
-        // if res.is_err() {
-        //     return Err(ElasticError::Response(res.err().unwrap().to_string()));
-        // }
-        //
-        // return Ok(res.unwrap());
+                .await
+        };
+        let res = match bulk_is_idempotent(lines) {
+            true => self.api.send_idempotent(&self.retry, request).await,
+            false => self.api.send_non_idempotent(&self.retry, request).await,
+        };
+        self.finish(res, lines).await
     }
     pub async fn insert_index<T: serde::Serialize>(
         &self,
         index: &str,
         sources: Vec<T>,
-    ) -> Result<Response, ElasticError> {
-        let mut body: Vec<JsonBody<_>> = Vec::with_capacity(4);
+    ) -> Result<BulkResponse, ElasticError> {
+        let mut lines: Vec<Value> = Vec::with_capacity(sources.len() * 2);
         for source in sources {
-            body.push(json!({"index": {}}).into());
-            body.push(json!(source).into())
+            lines.push(json!({"index": {}}));
+            lines.push(json!(source))
         }
+        let lines = &lines;
         let res = self
             .api
-            .client
-            .bulk(BulkParts::Index(index))
-            .body(body)
-            .send()
+            .send_non_idempotent(&self.retry, |client| async move {
+                client
+                    .bulk(BulkParts::Index(index))
+                    .body(ndjson_body(lines))
+                    .send()
+                    .await
+            })
             .await;
-        if res.is_err() {
-            return Err(ElasticError::Response(res.err().unwrap().to_string()));
-        }
-        return Ok(res.unwrap());
+        self.finish(res, lines).await
     }
     pub async fn insert_index_by_id<T: serde::Serialize>(
         &self,
@@ -608,64 +1463,73 @@ impl BulkApi<'_> {
         id: &str,
         source: T,
         refresh: bool,
-    ) -> Result<Response, ElasticError> {
-        let mut body: Vec<JsonBody<_>> = Vec::with_capacity(4);
-        body.push(json!({"index": {"_id":id}}).into());
-        body.push(json!(source).into());
-
+    ) -> Result<BulkResponse, ElasticError> {
+        let lines = &[json!({"index": {"_id":id}}), json!(source)];
         let res = self
             .api
-            .client
-            .bulk(BulkParts::Index(index))
-            .body(body)
-            .refresh(bool_to_refresh(refresh))
-            .send()
+            .send_idempotent(&self.retry, |client| async move {
+                client
+                    .bulk(BulkParts::Index(index))
+                    .body(ndjson_body(lines))
+                    .refresh(bool_to_refresh(refresh))
+                    .send()
+                    .await
+            })
             .await;
-        if res.is_err() {
-            return Err(ElasticError::Response(res.err().unwrap().to_string()));
-        }
-        return Ok(res.unwrap());
+        self.finish(res, lines).await
     }
 }
 
 /// https://www.elastic.co/guide/en/elasticsearch/reference/current/docs-index_.html
-pub struct IndexApi<'a> {
-    api: &'a ElasticApi,
+pub struct IndexApi {
+    api: ElasticApi,
+    retry: Option<RetryPolicy>,
+    routing: Option<String>,
 }
 
-impl IndexApi<'_> {
+impl IndexApi {
     pub fn new(api: &ElasticApi) -> IndexApi {
-        IndexApi { api }
+        IndexApi {
+            api: api.clone(),
+            retry: None,
+            routing: None,
+        }
+    }
+    /// Retry policy for this call, replacing the client policy.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+    /// Shard routing value the document was indexed with.
+    pub fn routing(mut self, routing: &str) -> Self {
+        self.routing = Some(routing.to_string());
+        self
     }
 }
 
-impl IndexApi<'_> {
+impl IndexApi {
     pub async fn create<T: serde::Serialize>(
         &self,
         index: &str,
         source: T,
         refresh: bool,
     ) -> Result<(), ElasticError> {
+        let source = &source;
+        let routing = self.routing.as_deref();
         let res = self
             .api
-            .client
-            .index(IndexParts::Index(index))
-            .refresh(bool_to_refresh(refresh))
-            .body(source)
-            .send()
+            .send_non_idempotent(&self.retry, |client| async move {
+                let mut request = client
+                    .index(IndexParts::Index(index))
+                    .refresh(bool_to_refresh(refresh))
+                    .body(source);
+                if let Some(routing) = routing {
+                    request = request.routing(routing);
+                }
+                request.send().await
+            })
             .await;
-        if res.is_err() {
-            return Err(ElasticError::Response(res.unwrap_err().to_string()));
-        }
-        let code = res.as_ref().unwrap().status_code();
-        if code == 404 {
-            return Err(ElasticError::NotFound(format!("not found entity")));
-        }
-        let res = res.unwrap();
-        // println!("status: {}",code);
-        if res.status_code() != 200 && res.status_code() != 201 {
-            return Err(ElasticError::Response(res.text().await.unwrap_or_default()));
-        }
+        check_response(res).await?;
         Ok(())
     }
     pub async fn doc<T: serde::Serialize>(
@@ -675,42 +1539,47 @@ impl IndexApi<'_> {
         source: T,
         refresh: bool,
     ) -> Result<(), ElasticError> {
+        let source = &source;
+        let routing = self.routing.as_deref();
         let res = self
             .api
-            .client
-            .index(IndexParts::IndexId(index, id))
-            .refresh(bool_to_refresh(refresh))
-            .body(source)
-            .send()
+            .send_idempotent(&self.retry, |client| async move {
+                let mut request = client
+                    .index(IndexParts::IndexId(index, id))
+                    .refresh(bool_to_refresh(refresh))
+                    .body(source);
+                if let Some(routing) = routing {
+                    request = request.routing(routing);
+                }
+                request.send().await
+            })
             .await;
-        if res.is_err() {
-            return Err(ElasticError::Response(res.unwrap_err().to_string()));
-        }
-        let code = res.as_ref().unwrap().status_code();
-        if code == 404 {
-            return Err(ElasticError::NotFound(format!("not found entity: {}", id)));
-        }
-        let res = res.unwrap();
-        // println!("status: {}",code);
-        if res.status_code() != 200 && res.status_code() != 201 {
-            return Err(ElasticError::Response(res.text().await.unwrap_or_default()));
-        }
+        check_response(res).await?;
         Ok(())
     }
 }
 
 /// https://www.elastic.co/guide/en/elasticsearch/reference/current/docs-update-by-query.html
-pub struct UpdateByQuery<'a> {
-    api: &'a ElasticApi,
+pub struct UpdateByQuery {
+    api: ElasticApi,
+    retry: Option<RetryPolicy>,
 }
 
-impl UpdateByQuery<'_> {
+impl UpdateByQuery {
     pub fn new(api: &ElasticApi) -> UpdateByQuery {
-        UpdateByQuery { api }
+        UpdateByQuery {
+            api: api.clone(),
+            retry: None,
+        }
+    }
+    /// Retry policy for this call, replacing the client policy.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
     }
 }
 
-impl UpdateByQuery<'_> {
+impl UpdateByQuery {
     pub async fn index(
         &self,
         index: &str,
@@ -719,39 +1588,41 @@ impl UpdateByQuery<'_> {
     ) -> Result<(), ElasticError> {
         let res = self
             .api
-            .client
-            .update_by_query(UpdateByQueryParts::Index(&[index]))
-            .refresh(refresh)
-            .body(query_builder.build())
-            .send()
+            .send_non_idempotent(&self.retry, |client| async move {
+                client
+                    .update_by_query(UpdateByQueryParts::Index(&[index]))
+                    .refresh(refresh)
+                    .body(query_builder.build())
+                    .send()
+                    .await
+            })
             .await;
-        if res.is_err() {
-            return Err(ElasticError::Response(res.unwrap_err().to_string()));
-        }
-        let code = res.as_ref().unwrap().status_code();
-        if code == 404 {
-            return Err(ElasticError::NotFound(format!("not found entity")));
-        }
-        let res = res.unwrap();
-        if res.status_code() != 200 {
-            return Err(ElasticError::Response(res.text().await.unwrap_or_default()));
-        }
+        check_response(res).await?;
         Ok(())
     }
 }
 
 /// https://www.elastic.co/guide/en/elasticsearch/reference/current/docs-delete-by-query.html
-pub struct DeleteByQueryApi<'a> {
-    api: &'a ElasticApi,
+pub struct DeleteByQueryApi {
+    api: ElasticApi,
+    retry: Option<RetryPolicy>,
 }
 
-impl DeleteByQueryApi<'_> {
+impl DeleteByQueryApi {
     pub fn new(api: &ElasticApi) -> DeleteByQueryApi {
-        DeleteByQueryApi { api }
+        DeleteByQueryApi {
+            api: api.clone(),
+            retry: None,
+        }
+    }
+    /// Retry policy for this call, replacing the client policy.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
     }
 }
 
-impl DeleteByQueryApi<'_> {
+impl DeleteByQueryApi {
     pub async fn index(
         &self,
         index: &str,
@@ -760,36 +1631,37 @@ impl DeleteByQueryApi<'_> {
     ) -> Result<(), ElasticError> {
         let res = self
             .api
-            .client
-            .delete_by_query(DeleteByQueryParts::Index(&[index]))
-            .body(query_builder.build())
-            .refresh(refresh)
-            .send()
+            .send_non_idempotent(&self.retry, |client| async move {
+                client
+                    .delete_by_query(DeleteByQueryParts::Index(&[index]))
+                    .body(query_builder.build())
+                    .refresh(refresh)
+                    .send()
+                    .await
+            })
             .await;
-        if res.is_err() {
-            return Err(ElasticError::Response(res.unwrap_err().to_string()));
-        }
-        let code = res.as_ref().unwrap().status_code();
-        if code == 404 {
-            return Err(ElasticError::NotFound(format!("not found index")));
-        }
-        let res = res.unwrap();
-        // println!("status: {}",code);
-        if res.status_code() != 200 && res.status_code() != 201 {
-            return Err(ElasticError::Response(res.text().await.unwrap_or_default()));
-        }
+        check_response(res).await?;
         Ok(())
     }
 }
 
 /// https://www.elastic.co/guide/en/elasticsearch/reference/current/docs-delete-by-query.html
-pub struct IlmApi<'a> {
-    api: &'a ElasticApi,
+pub struct IlmApi {
+    api: ElasticApi,
+    retry: Option<RetryPolicy>,
 }
 
-impl IlmApi<'_> {
+impl IlmApi {
     pub fn new(api: &ElasticApi) -> IlmApi {
-        IlmApi { api }
+        IlmApi {
+            api: api.clone(),
+            retry: None,
+        }
+    }
+    /// Retry policy for this call, replacing the client policy.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
     }
 }
 
@@ -797,35 +1669,26 @@ impl IlmApi<'_> {
 struct Acknowledged {
     acknowledged: bool,
 }
-impl IlmApi<'_> {
+impl IlmApi {
     pub async fn put_lifecycle<T: Serialize>(
         &self,
         ilm_name: &str,
         value: T,
-    ) -> Result<bool, ElasticError>
-    {
-        let res = match self
+    ) -> Result<bool, ElasticError> {
+        let value = &value;
+        let res = self
             .api
-            .client
-            .ilm()
-            .put_lifecycle(IlmPutLifecycleParts::Policy(ilm_name))
-            .body(value)
-            .send()
-            .await {
-            Ok(v) => v,
-            Err(e) => { return Err(ElasticError::Response(e.to_string())) }
-        };
-
-        let code = res.status_code();
-        if code == 404 {
-            return Err(ElasticError::NotFound("not found ILM".to_string()));
-        }
-        if res.status_code() != 200 && res.status_code() != 201 {
-            return Err(ElasticError::Response(res.text().await.unwrap_or_default()));
-        }
-        match res.json::<Acknowledged>().await {
-            Ok(v) => { Ok(v.acknowledged) }
-            Err(_) => { Ok(false) }
-        }
+            .send_idempotent(&self.retry, |client| async move {
+                client
+                    .ilm()
+                    .put_lifecycle(IlmPutLifecycleParts::Policy(ilm_name))
+                    .body(value)
+                    .send()
+                    .await
+            })
+            .await;
+        parse_response::<Acknowledged>(res)
+            .await
+            .map(|v| v.acknowledged)
     }
 }
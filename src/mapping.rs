@@ -0,0 +1,335 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+
+/// Index mapping of a struct, usually generated with
+/// `#[derive(ElasticMapping)]`.
+///
+/// ```
+/// use uiuifree_elastic::ElasticMapping;
+///
+/// #[derive(ElasticMapping)]
+/// #[elastic(dynamic = "strict")]
+/// struct Article {
+///     #[elastic(text, analyzer = "standard", fields(raw = "keyword"))]
+///     title: String,
+///     tags: Vec<String>,
+///     #[elastic(date, format = "yyyy-MM-dd HH:mm:ss")]
+///     created_at: Option<String>,
+///     #[elastic(index = false)]
+///     thumbnail: String,
+///     #[elastic(nested)]
+///     comments: Vec<Comment>,
+/// }
+///
+/// #[derive(ElasticMapping)]
+/// struct Comment {
+///     body: String,
+///     likes: u32,
+/// }
+///
+/// assert_eq!(Article::mapping()["properties"]["comments"]["type"], "nested");
+/// ```
+///
+/// Field attributes:
+///
+/// | attribute | effect |
+/// |---|---|
+/// | `keyword`, `text`, `date`, `type = "..."` | field type |
+/// | `analyzer`, `search_analyzer`, `normalizer`, `format` | passed through |
+/// | `index = false`, `doc_values = false`, `ignore_above = 256` | passed through |
+/// | `nested`, `object` | struct fields as `nested` or `object` |
+/// | `fields(raw = "keyword", kana(type = "text", analyzer = "kana"))` | multi-fields |
+/// | `rename = "..."`, `skip` | field name, exclusion (`serde(rename/skip)` is honoured too) |
+///
+/// `#[elastic(dynamic = "strict")]` on the struct sets `dynamic`. Serde's
+/// `rename_all` on the struct and `flatten` on a field shape the mapping the
+/// way they shape the document.
+pub trait ElasticMapping {
+    /// The `properties` object.
+    fn properties() -> Value;
+
+    /// `dynamic` setting of the mapping.
+    fn dynamic() -> Option<&'static str> {
+        None
+    }
+
+    /// The `mappings` object.
+    fn mapping() -> Value {
+        let mut mapping = json!({ "properties": Self::properties() });
+        if let Some(dynamic) = Self::dynamic() {
+            mapping["dynamic"] = json!(dynamic);
+        }
+        mapping
+    }
+
+    /// Create index request body.
+    fn index_body() -> Value {
+        json!({ "mappings": Self::mapping() })
+    }
+}
+
+/// Mapping of a field of this Rust type, before field attributes apply.
+pub trait FieldMapping {
+    fn field_mapping() -> Value;
+}
+
+macro_rules! field_mapping {
+    ($name:expr, $($t:ty),+) => {
+        $(impl FieldMapping for $t {
+            fn field_mapping() -> Value {
+                json!({ "type": $name })
+            }
+        })+
+    };
+}
+
+field_mapping!("keyword", String, str, char);
+field_mapping!("boolean", bool);
+field_mapping!("byte", i8);
+field_mapping!("short", i16, u8);
+field_mapping!("integer", i32, u16);
+field_mapping!("long", i64, isize, u32);
+field_mapping!("unsigned_long", u64, usize);
+field_mapping!("float", f32);
+field_mapping!("double", f64);
+field_mapping!("object", Value);
+
+impl<T: FieldMapping> FieldMapping for Option<T> {
+    fn field_mapping() -> Value {
+        T::field_mapping()
+    }
+}
+
+impl<T: FieldMapping> FieldMapping for Vec<T> {
+    fn field_mapping() -> Value {
+        T::field_mapping()
+    }
+}
+
+impl<T: FieldMapping> FieldMapping for [T] {
+    fn field_mapping() -> Value {
+        T::field_mapping()
+    }
+}
+
+impl<V> FieldMapping for HashMap<String, V> {
+    fn field_mapping() -> Value {
+        json!({ "type": "object" })
+    }
+}
+
+impl<V> FieldMapping for BTreeMap<String, V> {
+    fn field_mapping() -> Value {
+        json!({ "type": "object" })
+    }
+}
+
+/// Used by the derive macro.
+#[doc(hidden)]
+pub mod __private {
+    pub use serde_json::Value;
+
+    use serde_json::Map;
+
+    /// Sets a mapping parameter. Changing `type` away from an object type
+    /// drops the sub-properties.
+    pub fn set(field: &mut Value, key: &str, value: Value) {
+        if key == "type" && !matches!(value.as_str(), Some("object" | "nested")) {
+            if let Some(v) = field.as_object_mut() {
+                v.remove("properties");
+            }
+        }
+        if !field.is_object() {
+            *field = Value::Object(Map::new());
+        }
+        field[key] = value;
+    }
+
+    pub fn set_sub_field(field: &mut Value, name: &str, sub_field: Value) {
+        if !field["fields"].is_object() {
+            field["fields"] = Value::Object(Map::new());
+        }
+        field["fields"][name] = sub_field;
+    }
+
+    pub fn properties(fields: Vec<(&str, Value)>) -> Value {
+        Value::Object(
+            fields
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        )
+    }
+
+    /// Merges the properties of a `#[serde(flatten)]` field into the
+    /// properties of its struct.
+    pub fn flatten(properties: &mut Value, field: Value) {
+        if let (Some(properties), Value::Object(mut field)) = (properties.as_object_mut(), field) {
+            if let Some(Value::Object(v)) = field.remove("properties") {
+                properties.extend(v);
+            }
+        }
+    }
+
+    pub fn object<T: super::ElasticMapping>() -> Value {
+        let mut field = serde_json::json!({ "type": "object", "properties": T::properties() });
+        if let Some(dynamic) = T::dynamic() {
+            field["dynamic"] = serde_json::json!(dynamic);
+        }
+        field
+    }
+}
+
+/// Differences between an expected mapping and the mapping of a live index.
+/// Field paths are dotted, multi-fields included (`title.raw`).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MappingDiff {
+    /// In the index but not expected: documents carry data the type drops.
+    pub missing_fields: Vec<String>,
+    /// Expected but not in the index: writes rely on dynamic mapping, or fail
+    /// when it is strict.
+    pub unmapped_fields: Vec<String>,
+    pub type_mismatches: Vec<MappingMismatch>,
+    /// Not expected and shaped like Elasticsearch's dynamic string mapping
+    /// (`text` with a `keyword` sub-field), so likely added by a stray write.
+    pub dynamic_fields: Vec<String>,
+    /// `dynamic` setting differs.
+    pub dynamic_setting: Option<MappingMismatch>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MappingMismatch {
+    pub path: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl MappingDiff {
+    /// Compares two mappings, each either a `mappings` object or a create
+    /// index body holding one.
+    pub fn compare(expected: &Value, actual: &Value) -> MappingDiff {
+        let expected = mappings_of(expected);
+        let actual = mappings_of(actual);
+        let mut diff = MappingDiff::default();
+        let dynamic = |v: &Value| match &v["dynamic"] {
+            Value::Null => "true".to_string(),
+            Value::String(v) => v.to_string(),
+            v => v.to_string(),
+        };
+        if dynamic(expected) != dynamic(actual) {
+            diff.dynamic_setting = Some(MappingMismatch {
+                path: String::new(),
+                expected: dynamic(expected),
+                actual: dynamic(actual),
+            });
+        }
+        diff.compare_properties("", &expected["properties"], &actual["properties"]);
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &MappingDiff::default()
+    }
+
+    /// Adds the entries of `other` that are not in `self` yet.
+    pub fn merge(&mut self, other: MappingDiff) {
+        fn extend<T: PartialEq>(target: &mut Vec<T>, values: Vec<T>) {
+            for v in values {
+                if !target.contains(&v) {
+                    target.push(v);
+                }
+            }
+        }
+        extend(&mut self.missing_fields, other.missing_fields);
+        extend(&mut self.unmapped_fields, other.unmapped_fields);
+        extend(&mut self.type_mismatches, other.type_mismatches);
+        extend(&mut self.dynamic_fields, other.dynamic_fields);
+        if self.dynamic_setting.is_none() {
+            self.dynamic_setting = other.dynamic_setting;
+        }
+    }
+
+    fn compare_properties(&mut self, prefix: &str, expected: &Value, actual: &Value) {
+        let empty = Map::new();
+        let expected = expected.as_object().unwrap_or(&empty);
+        let actual = actual.as_object().unwrap_or(&empty);
+        for (name, field) in expected {
+            let path = format!("{}{}", prefix, name);
+            match actual.get(name) {
+                Some(live) => self.compare_field(&path, field, live),
+                None => self.unmapped_fields.push(path),
+            }
+        }
+        for (name, live) in actual {
+            if expected.contains_key(name) {
+                continue;
+            }
+            let path = format!("{}{}", prefix, name);
+            match is_dynamic_string(live) {
+                true => self.dynamic_fields.push(path),
+                false => self.missing_fields.push(path),
+            }
+        }
+    }
+
+    fn compare_field(&mut self, path: &str, expected: &Value, actual: &Value) {
+        let (expected_type, actual_type) = (field_type(expected), field_type(actual));
+        if expected_type != actual_type {
+            self.type_mismatches.push(MappingMismatch {
+                path: path.to_string(),
+                expected: expected_type.to_string(),
+                actual: actual_type.to_string(),
+            });
+            return;
+        }
+        let prefix = format!("{}.", path);
+        if expected.get("properties").is_some() || actual.get("properties").is_some() {
+            self.compare_properties(&prefix, &expected["properties"], &actual["properties"]);
+        }
+        if expected.get("fields").is_some() || actual.get("fields").is_some() {
+            self.compare_properties(&prefix, &expected["fields"], &actual["fields"]);
+        }
+    }
+}
+
+impl Display for MappingDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(v) = &self.dynamic_setting {
+            writeln!(f, "dynamic: expected {}, found {}", v.expected, v.actual)?;
+        }
+        for v in &self.type_mismatches {
+            writeln!(f, "{}: expected {}, found {}", v.path, v.expected, v.actual)?;
+        }
+        for v in &self.unmapped_fields {
+            writeln!(f, "{}: not mapped in the index", v)?;
+        }
+        for v in &self.missing_fields {
+            writeln!(f, "{}: missing from the type", v)?;
+        }
+        for v in &self.dynamic_fields {
+            writeln!(f, "{}: added by dynamic mapping", v)?;
+        }
+        Ok(())
+    }
+}
+
+fn mappings_of(value: &Value) -> &Value {
+    match value.get("mappings") {
+        Some(v) => v,
+        None => value,
+    }
+}
+
+fn field_type(field: &Value) -> &str {
+    match field["type"].as_str() {
+        Some(v) => v,
+        // Object fields leave `type` out.
+        None => "object",
+    }
+}
+
+fn is_dynamic_string(field: &Value) -> bool {
+    field["type"] == "text" && field["fields"]["keyword"]["type"] == "keyword"
+}
@@ -0,0 +1,596 @@
+use crate::error::ElasticError;
+use crate::retry::RetryPolicy;
+use crate::{parse_response, ElasticApi, SearchApi};
+use elastic_parser::Hit;
+use elastic_query_builder::QueryBuilder;
+use elasticsearch::{ClearScrollParts, OpenPointInTimeParts, ScrollParts, SearchParts};
+use futures_util::lock::Mutex;
+use futures_util::stream::{self, Stream, StreamExt, TryStreamExt};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Hits of a search walked page by page.
+pub type HitStream<T> = ItemStream<Hit<T>>;
+
+type ItemStream<H> = Pin<Box<dyn Stream<Item = Result<H, ElasticError>> + Send>>;
+
+impl SearchApi {
+    /// Streams every hit of `query` over `index`, paging with `search_after`
+    /// inside a point in time kept alive for `keep_alive` between pages.
+    ///
+    /// The query's sort is used with a `_shard_doc` tiebreaker appended, and
+    /// its size is the page size. The point in time is closed once the last
+    /// page is read, and in the background when the stream is dropped early.
+    ///
+    /// ```no_run
+    /// # async fn run(api: uiuifree_elastic::ElasticApi) {
+    /// use futures_util::TryStreamExt;
+    /// use serde_json::{json, Value};
+    /// use uiuifree_elastic::elastic_query_builder::QueryBuilder;
+    ///
+    /// let mut query = QueryBuilder::new();
+    /// query.set_size(1000);
+    /// query.set_sort(json!([{"created_at": "asc"}]));
+    /// let mut hits = api.search().search_after::<Value>("logs", &query, "1m");
+    /// while let Some(hit) = hits.try_next().await.unwrap() {
+    ///     println!("{:?}", hit._id);
+    /// }
+    /// # }
+    /// ```
+    pub fn search_after<T>(
+        &self,
+        index: &str,
+        query: &QueryBuilder,
+        keep_alive: &str,
+    ) -> HitStream<T>
+    where
+        T: DeserializeOwned + Clone + Send + 'static,
+    {
+        let pit = Arc::new(SharedPit::new(index));
+        search_after_stream(self, page_body(query), pit, keep_alive)
+    }
+
+    /// [`search_after`](SearchApi::search_after) split into `slices`
+    /// streams over one point in time, to be read concurrently. The point in
+    /// time is closed when the last slice ends or is dropped.
+    ///
+    /// Use [`merge_slices`] for a single stream.
+    pub fn search_after_slices<T>(
+        &self,
+        index: &str,
+        query: &QueryBuilder,
+        keep_alive: &str,
+        slices: u32,
+    ) -> Vec<HitStream<T>>
+    where
+        T: DeserializeOwned + Clone + Send + 'static,
+    {
+        let pit = Arc::new(SharedPit::new(index));
+        slice_bodies(page_body(query), slices)
+            .into_iter()
+            .map(|body| search_after_stream(self, body, pit.clone(), keep_alive))
+            .collect()
+    }
+
+    /// Streams every hit of `query` over `index` with the scroll API,
+    /// renewing the scroll for `keep_alive` on each page. The query's size is
+    /// the page size.
+    ///
+    /// The scroll is cleared once the last page is read, and in the
+    /// background when a request fails or the stream is dropped early.
+    pub fn scroll_stream<T>(
+        &self,
+        index: &[&str],
+        query: &QueryBuilder,
+        keep_alive: &str,
+    ) -> HitStream<T>
+    where
+        T: DeserializeOwned + Clone + Send + 'static,
+    {
+        scroll_stream(self, scroll_body(query), index, keep_alive)
+    }
+
+    /// [`scroll_stream`](SearchApi::scroll_stream) split into `slices`
+    /// sliced scrolls, to be read concurrently. Each slice clears its own
+    /// scroll.
+    ///
+    /// Use [`merge_slices`] for a single stream.
+    pub fn scroll_slices<T>(
+        &self,
+        index: &[&str],
+        query: &QueryBuilder,
+        keep_alive: &str,
+        slices: u32,
+    ) -> Vec<HitStream<T>>
+    where
+        T: DeserializeOwned + Clone + Send + 'static,
+    {
+        slice_bodies(scroll_body(query), slices)
+            .into_iter()
+            .map(|body| scroll_stream(self, body, index, keep_alive))
+            .collect()
+    }
+
+    /// [`search_after`](SearchApi::search_after) over a prepared body,
+    /// each hit read as `H`.
+    pub(crate) fn search_after_body<H>(
+        &self,
+        index: &str,
+        body: Value,
+        keep_alive: &str,
+    ) -> ItemStream<H>
+    where
+        H: DeserializeOwned + Send + 'static,
+    {
+        search_after_stream(self, body, Arc::new(SharedPit::new(index)), keep_alive)
+    }
+
+    /// Opens a point in time over `index`, kept alive for `keep_alive`
+    /// between searches. Search it with [`pit`](SearchApi::pit).
+    ///
+    /// The guard closes the point in time when dropped; hand it over with
+    /// [`PitGuard::into_id`] when it has to outlive the guard.
+    pub async fn open_pit(&self, index: &str, keep_alive: &str) -> Result<PitGuard, ElasticError> {
+        let id = open_pit(&self.api, &self.retry, index, keep_alive).await?;
+        Ok(PitGuard {
+            id: id.clone(),
+            keep_alive: keep_alive.to_string(),
+            closer: Closer::new(&self.api, Context::Pit(id)),
+        })
+    }
+
+    /// Closes a point in time. Ids that already expired are ignored.
+    pub async fn close_pit(&self, id: &str) -> Result<(), ElasticError> {
+        close_pit(&self.api, id).await
+    }
+
+    /// Frees scroll contexts before their keep-alive runs out. Ids that
+    /// already expired are ignored.
+    pub async fn clear_scroll(&self, scroll_ids: &[&str]) -> Result<(), ElasticError> {
+        clear_scroll(&self.api, &self.retry, scroll_ids).await
+    }
+}
+
+/// Reads up to `concurrency` slices at a time into one stream, in no
+/// particular order. An error ends only the slice it came from.
+pub fn merge_slices<T>(slices: Vec<HitStream<T>>, concurrency: usize) -> HitStream<T>
+where
+    T: Clone + Send + 'static,
+{
+    stream::iter(slices)
+        .flatten_unordered(concurrency.max(1))
+        .boxed()
+}
+
+fn hit_stream<S, H, F, Fut>(state: S, next_page: F) -> ItemStream<H>
+where
+    S: Send + 'static,
+    H: Send + 'static,
+    F: FnMut(S) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Option<(Vec<H>, S)>, ElasticError>> + Send + 'static,
+{
+    stream::try_unfold(state, next_page)
+        .map_ok(|hits| stream::iter(hits.into_iter().map(Ok)))
+        .try_flatten()
+        .boxed()
+}
+
+fn search_after_stream<H>(
+    search: &SearchApi,
+    body: Value,
+    pit: Arc<SharedPit>,
+    keep_alive: &str,
+) -> ItemStream<H>
+where
+    H: DeserializeOwned + Send + 'static,
+{
+    let state = SearchAfter {
+        api: search.api.clone(),
+        retry: search.retry.clone(),
+        keep_alive: keep_alive.to_string(),
+        body,
+        pit,
+        done: false,
+    };
+    hit_stream(state, |mut state: SearchAfter| async move {
+        Ok(state.next_page::<H>().await?.map(|hits| (hits, state)))
+    })
+}
+
+fn scroll_stream<H>(
+    search: &SearchApi,
+    body: Value,
+    index: &[&str],
+    keep_alive: &str,
+) -> ItemStream<H>
+where
+    H: DeserializeOwned + Send + 'static,
+{
+    let state = Scroll {
+        api: search.api.clone(),
+        retry: search.retry.clone(),
+        index: index.iter().map(|v| v.to_string()).collect(),
+        keep_alive: keep_alive.to_string(),
+        body,
+        scroll: Closer::default(),
+        done: false,
+    };
+    hit_stream(state, |mut state: Scroll| async move {
+        Ok(state.next_page::<H>().await?.map(|hits| (hits, state)))
+    })
+}
+
+/// One body per slice, or `body` itself for a single slice.
+fn slice_bodies(body: Value, slices: u32) -> Vec<Value> {
+    if slices <= 1 {
+        return vec![body];
+    }
+    (0..slices)
+        .map(|id| {
+            let mut body = body.clone();
+            body["slice"] = json!({ "id": id, "max": slices });
+            body
+        })
+        .collect()
+}
+
+/// A point in time shared by the slices reading it, opened by the first.
+struct SharedPit {
+    index: String,
+    closer: Mutex<Closer>,
+}
+
+impl SharedPit {
+    fn new(index: &str) -> SharedPit {
+        SharedPit {
+            index: index.to_string(),
+            closer: Mutex::new(Closer::default()),
+        }
+    }
+
+    async fn id(
+        &self,
+        api: &ElasticApi,
+        retry: &Option<RetryPolicy>,
+        keep_alive: &str,
+    ) -> Result<String, ElasticError> {
+        let mut closer = self.closer.lock().await;
+        if let Some(Context::Pit(id)) = &closer.context {
+            return Ok(id.clone());
+        }
+        let id = open_pit(api, retry, &self.index, keep_alive).await?;
+        *closer = Closer::new(api, Context::Pit(id.clone()));
+        Ok(id)
+    }
+
+    async fn update(&self, id: &str) {
+        let mut closer = self.closer.lock().await;
+        if closer.context.is_some() {
+            closer.context = Some(Context::Pit(id.to_string()));
+        }
+    }
+
+    async fn close(&self) -> Result<(), ElasticError> {
+        self.closer.lock().await.close().await
+    }
+}
+
+struct SearchAfter {
+    api: ElasticApi,
+    retry: Option<RetryPolicy>,
+    keep_alive: String,
+    body: Value,
+    pit: Arc<SharedPit>,
+    done: bool,
+}
+
+impl SearchAfter {
+    async fn next_page<H>(&mut self) -> Result<Option<Vec<H>>, ElasticError>
+    where
+        H: DeserializeOwned,
+    {
+        if self.done {
+            return Ok(None);
+        }
+        let pit_id = self
+            .pit
+            .id(&self.api, &self.retry, &self.keep_alive)
+            .await?;
+        self.body["pit"] = json!({ "id": pit_id, "keep_alive": self.keep_alive });
+
+        let body = &self.body;
+        let res = self
+            .api
+            .send_idempotent(&self.retry, |client| async move {
+                client.search(SearchParts::None).body(body).send().await
+            })
+            .await;
+        let page: Value = parse_response(res).await?;
+        if let Some(id) = page["pit_id"].as_str() {
+            self.pit.update(id).await;
+        }
+
+        let hits = page["hits"]["hits"].as_array().cloned().unwrap_or_default();
+        let size = self.body["size"].as_u64().unwrap_or(0) as usize;
+        match hits.last().map(|v| v["sort"].clone()) {
+            Some(sort) if !sort.is_null() && hits.len() >= size => {
+                self.body["search_after"] = sort;
+            }
+            _ => {
+                self.done = true;
+                // Other slices still reading close it when they end.
+                if Arc::strong_count(&self.pit) == 1 {
+                    self.pit.close().await?;
+                }
+            }
+        }
+        parse_hits(hits)
+    }
+}
+
+struct Scroll {
+    api: ElasticApi,
+    retry: Option<RetryPolicy>,
+    index: Vec<String>,
+    keep_alive: String,
+    body: Value,
+    scroll: Closer,
+    done: bool,
+}
+
+impl Scroll {
+    async fn next_page<H>(&mut self) -> Result<Option<Vec<H>>, ElasticError>
+    where
+        H: DeserializeOwned,
+    {
+        if self.done {
+            return Ok(None);
+        }
+        let keep_alive = self.keep_alive.as_str();
+        let res = match &self.scroll.context {
+            Some(Context::Scroll(id)) => {
+                // In the body: scroll ids can outgrow a URL. Not repeated,
+                // as each call moves the cursor and a repeat would skip a page.
+                let body = json!({ "scroll": keep_alive, "scroll_id": id });
+                let body = &body;
+                self.api
+                    .send_non_idempotent(&self.retry, |client| async move {
+                        client.scroll(ScrollParts::None).body(body).send().await
+                    })
+                    .await
+            }
+            _ => {
+                let index: Vec<&str> = self.index.iter().map(|v| v.as_str()).collect();
+                let index = index.as_slice();
+                let body = &self.body;
+                self.api
+                    .send_idempotent(&self.retry, |client| async move {
+                        client
+                            .search(SearchParts::Index(index))
+                            .body(body)
+                            .scroll(keep_alive)
+                            .send()
+                            .await
+                    })
+                    .await
+            }
+        };
+        let page: Value = parse_response(res).await?;
+        if let Some(id) = page["_scroll_id"].as_str() {
+            match &mut self.scroll.context {
+                Some(Context::Scroll(v)) => *v = id.to_string(),
+                _ => self.scroll = Closer::new(&self.api, Context::Scroll(id.to_string())),
+            }
+        }
+
+        let hits = page["hits"]["hits"].as_array().cloned().unwrap_or_default();
+        if hits.is_empty() {
+            self.done = true;
+            self.scroll.close().await?;
+        }
+        parse_hits(hits)
+    }
+}
+
+/// `None` for an empty page, which ends the stream.
+fn parse_hits<H>(hits: Vec<Value>) -> Result<Option<Vec<H>>, ElasticError>
+where
+    H: DeserializeOwned,
+{
+    if hits.is_empty() {
+        return Ok(None);
+    }
+    hits.into_iter()
+        .map(|v| serde_json::from_value(v).map_err(|e| ElasticError::JsonParse(e.to_string())))
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
+
+/// Search body for point in time paging: the index goes into the point in
+/// time, aggregations are dropped and `_shard_doc` breaks sort ties.
+pub(crate) fn page_body(query: &QueryBuilder) -> Value {
+    let mut body = query.build();
+    if let Some(v) = body.as_object_mut() {
+        v.remove("aggs");
+    }
+    let mut sort = match query.get_sort() {
+        Value::Array(v) => v.clone(),
+        Value::Null => vec![],
+        v => vec![v.clone()],
+    };
+    let tiebreaker = sort.iter().any(|v| match v {
+        Value::String(v) => v == "_shard_doc",
+        Value::Object(v) => v.contains_key("_shard_doc"),
+        _ => false,
+    });
+    if !tiebreaker {
+        sort.push(json!({ "_shard_doc": "asc" }));
+    }
+    body["sort"] = json!(sort);
+    body["size"] = json!(query.get_size().max(1));
+    body["track_total_hits"] = json!(false);
+    body
+}
+
+fn scroll_body(query: &QueryBuilder) -> Value {
+    let mut body = query.build();
+    if query.get_size() > 0 {
+        body["size"] = json!(query.get_size());
+    }
+    body
+}
+
+pub(crate) async fn open_pit(
+    api: &ElasticApi,
+    retry: &Option<RetryPolicy>,
+    index: &str,
+    keep_alive: &str,
+) -> Result<String, ElasticError> {
+    let res = api
+        .send_idempotent(retry, |client| async move {
+            client
+                .open_point_in_time(OpenPointInTimeParts::Index(&[index]))
+                .keep_alive(keep_alive)
+                .send()
+                .await
+        })
+        .await;
+    let value: Value = parse_response(res).await?;
+    match value["id"].as_str() {
+        Some(v) => Ok(v.to_string()),
+        None => Err(ElasticError::JsonParse(value.to_string())),
+    }
+}
+
+pub(crate) async fn close_pit(api: &ElasticApi, id: &str) -> Result<(), ElasticError> {
+    let body = json!({ "id": id });
+    let body = &body;
+    let res = api
+        .send_idempotent(&None, |client| async move {
+            client.close_point_in_time().body(body).send().await
+        })
+        .await;
+    match parse_response::<Value>(res).await {
+        // Already expired.
+        Err(ElasticError::NotFound(_)) => Ok(()),
+        v => v.map(|_| ()),
+    }
+}
+
+pub(crate) async fn clear_scroll(
+    api: &ElasticApi,
+    retry: &Option<RetryPolicy>,
+    scroll_ids: &[&str],
+) -> Result<(), ElasticError> {
+    let body = json!({ "scroll_id": scroll_ids });
+    let body = &body;
+    let res = api
+        .send_idempotent(retry, |client| async move {
+            client
+                .clear_scroll(ClearScrollParts::None)
+                .body(body)
+                .send()
+                .await
+        })
+        .await;
+    match parse_response::<Value>(res).await {
+        // Already expired.
+        Err(ElasticError::NotFound(_)) => Ok(()),
+        v => v.map(|_| ()),
+    }
+}
+
+/// An open point in time, closed in the background when dropped.
+///
+/// ```no_run
+/// # async fn run(api: uiuifree_elastic::ElasticApi) {
+/// use serde_json::Value;
+/// use uiuifree_elastic::elastic_query_builder::QueryBuilder;
+///
+/// let pit = api.search().open_pit("products", "5m").await.unwrap();
+/// let page = api
+///     .search()
+///     .pit(pit.id(), pit.keep_alive())
+///     .search::<Value>(&[], &QueryBuilder::new())
+///     .await
+///     .unwrap();
+/// pit.close().await.unwrap();
+/// # }
+/// ```
+pub struct PitGuard {
+    id: String,
+    keep_alive: String,
+    closer: Closer,
+}
+
+impl PitGuard {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    pub fn keep_alive(&self) -> &str {
+        &self.keep_alive
+    }
+    /// Closes the point in time and waits for the response.
+    pub async fn close(mut self) -> Result<(), ElasticError> {
+        self.closer.close().await
+    }
+    /// Leaves the point in time open, to expire or be closed with
+    /// [`SearchApi::close_pit`].
+    pub fn into_id(mut self) -> String {
+        self.closer.context = None;
+        self.id.clone()
+    }
+}
+
+/// A point in time or scroll context held open on the cluster.
+enum Context {
+    Pit(String),
+    Scroll(String),
+}
+
+impl Context {
+    async fn close(self, api: &ElasticApi) -> Result<(), ElasticError> {
+        match self {
+            Context::Pit(id) => close_pit(api, &id).await,
+            Context::Scroll(id) => clear_scroll(api, &None, &[&id]).await,
+        }
+    }
+}
+
+/// Closes a search context when dropped, unless closed before.
+#[derive(Default)]
+struct Closer {
+    api: Option<ElasticApi>,
+    context: Option<Context>,
+}
+
+impl Closer {
+    fn new(api: &ElasticApi, context: Context) -> Closer {
+        Closer {
+            api: Some(api.clone()),
+            context: Some(context),
+        }
+    }
+
+    async fn close(&mut self) -> Result<(), ElasticError> {
+        match (&self.api, self.context.take()) {
+            (Some(api), Some(context)) => context.close(api).await,
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for Closer {
+    fn drop(&mut self) {
+        if let (Some(api), Some(context)) = (self.api.take(), self.context.take()) {
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                handle.spawn(async move {
+                    let _ = context.close(&api).await;
+                });
+            }
+        }
+    }
+}
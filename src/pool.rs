@@ -0,0 +1,307 @@
+use crate::error::ElasticError;
+use elasticsearch::http::transport::{Connection, ConnectionPool};
+use elasticsearch::http::Url;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_DEAD_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_MAX_DEAD_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+fn normalize(url: &Url) -> Url {
+    let mut url = url.clone();
+    if !url.path().ends_with('/') {
+        url.set_path(&format!("{}/", url.path()));
+    }
+    url
+}
+
+#[derive(Debug)]
+struct Node {
+    url: Url,
+    connection: Arc<Connection>,
+    failures: u32,
+    dead_until: Option<Instant>,
+}
+
+impl Node {
+    fn new(url: Url, connections: &Mutex<HashMap<String, Arc<Connection>>>) -> Node {
+        let url = normalize(&url);
+        let connection = connections
+            .lock()
+            .unwrap()
+            .entry(url.to_string())
+            .or_insert_with(|| Arc::new(Connection::new(url.clone())))
+            .clone();
+        Node {
+            connection,
+            url,
+            failures: 0,
+            dead_until: None,
+        }
+    }
+    fn is_alive(&self, now: Instant) -> bool {
+        match self.dead_until {
+            Some(v) => v <= now,
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PoolState {
+    nodes: RwLock<Vec<Node>>,
+    /// Every connection a node of this pool ever used, by url.
+    /// `ConnectionPool::next` hands out a borrow tied to the pool, so a
+    /// connection must outlive a reseed that drops its node; entries are
+    /// only removed with the pool.
+    connections: Mutex<HashMap<String, Arc<Connection>>>,
+    index: AtomicUsize,
+    dead_timeout: Duration,
+    max_dead_timeout: Duration,
+    sniff_interval: Option<Duration>,
+    sniff_on_connection_fault: bool,
+    last_sniff: Mutex<Option<Instant>>,
+    sniffing: AtomicBool,
+}
+
+/// Round-robin pool over several nodes.
+///
+/// Nodes reported as failing are skipped for `dead_timeout`, doubling on each
+/// consecutive failure up to `max_dead_timeout`, and are tried again once that
+/// expires. When every node is dead the one closest to resurrection is used.
+/// Attach the pool to an [`ElasticApi`](crate::ElasticApi) with
+/// [`with_pool`](crate::ElasticApi::with_pool) so failures are reported and
+/// sniffing runs.
+#[derive(Debug, Clone)]
+pub struct MultiNodeConnectionPool {
+    state: Arc<PoolState>,
+}
+
+impl MultiNodeConnectionPool {
+    /// A pool needs at least one node; an empty `urls` is an
+    /// [`ElasticError::Config`].
+    pub fn new(urls: Vec<Url>) -> Result<MultiNodeConnectionPool, ElasticError> {
+        MultiNodeConnectionPool::with_settings(urls, PoolSettings::default())
+    }
+
+    pub fn with_settings(
+        urls: Vec<Url>,
+        settings: PoolSettings,
+    ) -> Result<MultiNodeConnectionPool, ElasticError> {
+        if urls.is_empty() {
+            return Err(ElasticError::Config(
+                "connection pool needs at least one node".to_string(),
+            ));
+        }
+        let connections = Mutex::new(HashMap::new());
+        let nodes = urls
+            .into_iter()
+            .map(|v| Node::new(v, &connections))
+            .collect();
+        Ok(MultiNodeConnectionPool {
+            state: Arc::new(PoolState {
+                nodes: RwLock::new(nodes),
+                connections,
+                index: AtomicUsize::new(0),
+                dead_timeout: settings.dead_timeout,
+                max_dead_timeout: settings.max_dead_timeout,
+                sniff_interval: settings.sniff_interval,
+                sniff_on_connection_fault: settings.sniff_on_connection_fault,
+                last_sniff: Mutex::new(match settings.sniff_on_start {
+                    true => None,
+                    false => Some(Instant::now()),
+                }),
+                sniffing: AtomicBool::new(false),
+            }),
+        })
+    }
+
+    pub fn urls(&self) -> Vec<Url> {
+        let nodes = self.state.nodes.read().unwrap();
+        nodes.iter().map(|v| v.url.clone()).collect()
+    }
+
+    pub fn alive_urls(&self) -> Vec<Url> {
+        let now = Instant::now();
+        let nodes = self.state.nodes.read().unwrap();
+        nodes
+            .iter()
+            .filter(|v| v.is_alive(now))
+            .map(|v| v.url.clone())
+            .collect()
+    }
+
+    /// Marks the node serving `url` (any request url on that node) as dead.
+    pub fn mark_dead(&self, url: &Url) {
+        let mut nodes = self.state.nodes.write().unwrap();
+        if let Some(node) = find_node(&mut nodes, url) {
+            node.failures = node.failures.saturating_add(1);
+            let factor = 2u32.saturating_pow(node.failures - 1);
+            let timeout = self
+                .state
+                .dead_timeout
+                .saturating_mul(factor)
+                .min(self.state.max_dead_timeout);
+            node.dead_until = Some(Instant::now() + timeout);
+        }
+    }
+
+    pub fn mark_alive(&self, url: &Url) {
+        {
+            let nodes = self.state.nodes.read().unwrap();
+            let alive = nodes
+                .iter()
+                .find(|v| url.as_str().starts_with(v.url.as_str()))
+                .map(|v| v.failures == 0)
+                .unwrap_or(true);
+            if alive {
+                return;
+            }
+        }
+        let mut nodes = self.state.nodes.write().unwrap();
+        if let Some(node) = find_node(&mut nodes, url) {
+            node.failures = 0;
+            node.dead_until = None;
+        }
+    }
+
+    /// Replaces the node list, keeping the liveness of nodes that remain.
+    /// An empty `urls`, such as a sniff that found no http nodes, keeps the
+    /// current list.
+    pub fn reseed(&self, urls: Vec<Url>) {
+        if urls.is_empty() {
+            return;
+        }
+        let mut nodes = self.state.nodes.write().unwrap();
+        let mut previous: HashMap<String, Node> =
+            nodes.drain(..).map(|v| (v.url.to_string(), v)).collect();
+        for url in urls {
+            let url = normalize(&url);
+            let node = match previous.remove(url.as_str()) {
+                Some(v) => v,
+                None => Node::new(url, &self.state.connections),
+            };
+            if !nodes.iter().any(|v| v.url == node.url) {
+                nodes.push(node);
+            }
+        }
+    }
+
+    pub(crate) fn sniff_on_connection_fault(&self) -> bool {
+        self.state.sniff_on_connection_fault
+    }
+
+    /// Claims the next sniff when it is due. The caller must hand the claim
+    /// back through `finish_sniff`.
+    pub(crate) fn begin_sniff(&self, force: bool) -> bool {
+        if !force {
+            let last = self.state.last_sniff.lock().unwrap();
+            let due = match (*last, self.state.sniff_interval) {
+                (None, _) => true,
+                (Some(at), Some(interval)) => at.elapsed() >= interval,
+                (Some(_), None) => false,
+            };
+            if !due {
+                return false;
+            }
+        }
+        !self.state.sniffing.swap(true, Ordering::AcqRel)
+    }
+
+    pub(crate) fn finish_sniff(&self) {
+        *self.state.last_sniff.lock().unwrap() = Some(Instant::now());
+        self.state.sniffing.store(false, Ordering::Release);
+    }
+
+    pub(crate) fn scheme(&self) -> String {
+        let nodes = self.state.nodes.read().unwrap();
+        nodes
+            .first()
+            .map(|v| v.url.scheme().to_string())
+            .unwrap_or_else(|| "http".to_string())
+    }
+}
+
+fn find_node<'a>(nodes: &'a mut [Node], url: &Url) -> Option<&'a mut Node> {
+    nodes
+        .iter_mut()
+        .find(|v| url.as_str().starts_with(v.url.as_str()))
+}
+
+impl ConnectionPool for MultiNodeConnectionPool {
+    fn next(&self) -> &Connection {
+        let now = Instant::now();
+        let nodes = self.state.nodes.read().unwrap();
+        let alive = nodes.iter().filter(|v| v.is_alive(now)).count();
+        let node = match alive {
+            0 => nodes.iter().min_by_key(|v| v.dead_until),
+            _ => {
+                let i = self.state.index.fetch_add(1, Ordering::Relaxed) % alive;
+                nodes.iter().filter(|v| v.is_alive(now)).nth(i)
+            }
+        };
+        // The node list is never empty: the constructor rejects an empty one
+        // and `reseed` keeps the current list instead of emptying it.
+        let connection = Arc::as_ptr(&node.unwrap_or(&nodes[0]).connection);
+        // SAFETY: `state.connections` keeps a clone of every node's Arc and
+        // only drops it with the state, which `self` keeps alive for the
+        // lifetime of the returned borrow.
+        unsafe { &*connection }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PoolSettings {
+    pub dead_timeout: Duration,
+    pub max_dead_timeout: Duration,
+    /// Sniff before the first request.
+    pub sniff_on_start: bool,
+    /// Sniff again once this much time passed since the last sniff.
+    pub sniff_interval: Option<Duration>,
+    /// Sniff right after a node failed to respond.
+    pub sniff_on_connection_fault: bool,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        PoolSettings {
+            dead_timeout: DEFAULT_DEAD_TIMEOUT,
+            max_dead_timeout: DEFAULT_MAX_DEAD_TIMEOUT,
+            sniff_on_start: false,
+            sniff_interval: None,
+            sniff_on_connection_fault: false,
+        }
+    }
+}
+
+/// Node urls from a `GET _nodes/http` response.
+pub(crate) fn sniffed_urls(value: &Value, scheme: &str) -> Vec<Url> {
+    let mut urls = vec![];
+    let nodes = match value["nodes"].as_object() {
+        Some(v) => v,
+        None => return urls,
+    };
+    for node in nodes.values() {
+        let address = match node["http"]["publish_address"].as_str() {
+            Some(v) => v,
+            None => continue,
+        };
+        // "hostname/10.0.0.1:9200" keeps the hostname for TLS verification.
+        let address = match address.split_once('/') {
+            Some((host, ip_port)) if !host.is_empty() => match ip_port.rsplit_once(':') {
+                Some((_, port)) => format!("{}:{}", host, port),
+                None => ip_port.to_string(),
+            },
+            Some((_, ip_port)) => ip_port.to_string(),
+            None => address.to_string(),
+        };
+        if let Ok(url) = Url::parse(&format!("{}://{}", scheme, address)) {
+            urls.push(url);
+        }
+    }
+    urls.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    urls
+}
@@ -0,0 +1,163 @@
+use crate::error::ElasticError;
+use crate::{BulkOperation, BulkResponse, ElasticApi};
+use elastic_query_builder::QueryBuilder;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+
+/// A type stored as documents of one index.
+///
+/// ```
+/// use serde::{Deserialize, Serialize};
+/// use uiuifree_elastic::Document;
+///
+/// #[derive(Serialize, Deserialize, Clone)]
+/// struct User {
+///     id: u64,
+///     tenant: String,
+///     name: String,
+/// }
+///
+/// impl Document for User {
+///     fn index_name() -> &'static str {
+///         "users"
+///     }
+///     fn id(&self) -> String {
+///         self.id.to_string()
+///     }
+///     fn routing(&self) -> Option<String> {
+///         Some(self.tenant.clone())
+///     }
+/// }
+/// ```
+pub trait Document: Serialize + DeserializeOwned {
+    fn index_name() -> &'static str;
+    fn id(&self) -> String;
+    /// Routing value used when saving the document.
+    fn routing(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Reads and writes `T` by id, without repeating the index name.
+///
+/// Documents saved with a [`routing`](Document::routing) must be read and
+/// deleted through [`with_routing`](Repository::with_routing).
+pub struct Repository<T: Document> {
+    api: ElasticApi,
+    index: String,
+    routing: Option<String>,
+    refresh: bool,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Document> Clone for Repository<T> {
+    fn clone(&self) -> Self {
+        Repository {
+            api: self.api.clone(),
+            index: self.index.clone(),
+            routing: self.routing.clone(),
+            refresh: self.refresh,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Document> Repository<T> {
+    pub fn new(api: &ElasticApi) -> Repository<T> {
+        Repository {
+            api: api.clone(),
+            index: T::index_name().to_string(),
+            routing: None,
+            refresh: false,
+            _marker: PhantomData,
+        }
+    }
+    /// Uses another index or alias than [`Document::index_name`].
+    pub fn with_index(mut self, index: &str) -> Self {
+        self.index = index.to_string();
+        self
+    }
+    /// Routing for `find`, `exists` and `delete`.
+    pub fn with_routing(mut self, routing: &str) -> Self {
+        self.routing = Some(routing.to_string());
+        self
+    }
+    /// Refreshes the index after each write.
+    pub fn refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+    pub fn index(&self) -> &str {
+        &self.index
+    }
+
+    /// `None` when no document has this id.
+    pub async fn find(&self, id: &str) -> Result<Option<T>, ElasticError> {
+        let mut get = self.api.get();
+        if let Some(routing) = &self.routing {
+            get = get.routing(routing);
+        }
+        match get.source(&self.index, id).await {
+            Ok(v) => Ok(Some(v)),
+            Err(e) if e.is_not_found() && !e.is_index_not_found() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn exists(&self, id: &str) -> Result<bool, ElasticError> {
+        let mut get = self.api.get();
+        if let Some(routing) = &self.routing {
+            get = get.routing(routing);
+        }
+        get.exists(&self.index, id).await
+    }
+
+    pub async fn save(&self, doc: &T) -> Result<(), ElasticError> {
+        let mut index = self.api.index();
+        if let Some(routing) = doc.routing() {
+            index = index.routing(&routing);
+        }
+        index.doc(&self.index, &doc.id(), doc, self.refresh).await
+    }
+
+    /// Indexes every document in one bulk request and returns the bulk
+    /// response, empty without sending anything when `docs` is.
+    pub async fn save_all(&self, docs: &[T]) -> Result<BulkResponse, ElasticError> {
+        let operations: Vec<BulkOperation> = docs
+            .iter()
+            .map(|doc| {
+                let operation = BulkOperation::index(&self.index, doc).id(&doc.id());
+                match doc.routing() {
+                    Some(routing) => operation.routing(&routing),
+                    None => operation,
+                }
+            })
+            .collect();
+        self.api.bulk().operations(&operations, self.refresh).await
+    }
+
+    /// `false` when no document had this id.
+    pub async fn delete(&self, id: &str) -> Result<bool, ElasticError> {
+        let mut delete = self.api.delete().refresh(self.refresh);
+        if let Some(routing) = &self.routing {
+            delete = delete.routing(routing);
+        }
+        Ok(delete.doc(&self.index, id).await?.is_deleted())
+    }
+
+    /// Sources of the matching documents.
+    pub async fn search(&self, query: &QueryBuilder) -> Result<Vec<T>, ElasticError>
+    where
+        T: Clone + 'static,
+    {
+        let res = self.api.search().search::<T>(&[&self.index], query).await?;
+        Ok(res
+            .and_then(|v| v.hits)
+            .and_then(|v| v.hits)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|v| v._source)
+            .collect())
+    }
+}
@@ -0,0 +1,113 @@
+use elasticsearch::http::response::Response;
+use elasticsearch::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Retry with exponential backoff for rejected or unreachable requests.
+///
+/// The policy set on [`ElasticApi`](crate::ElasticApi) only applies to
+/// operations that are safe to repeat (reads, partial document updates,
+/// unconditional writes with an explicit id); deletes, bulk updates and
+/// conditional writes are not.
+/// A policy passed per call with `with_retry` applies to that call whatever
+/// the operation, so the caller decides for auto-id writes.
+///
+/// ```toml
+/// [retry]
+/// max_attempts = 5
+/// initial_backoff_ms = 200
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Attempts including the first one. `1` disables retrying.
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Sleep a random duration between half and all of the backoff.
+    pub jitter: bool,
+    pub retry_on_status: Vec<u16>,
+    pub retry_on_connection_error: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 10_000,
+            jitter: true,
+            retry_on_status: vec![429, 502, 503, 504],
+            retry_on_connection_error: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            ..RetryPolicy::default()
+        }
+    }
+    pub fn none() -> RetryPolicy {
+        RetryPolicy::new(1)
+    }
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> RetryPolicy {
+        self.initial_backoff_ms = initial.as_millis() as u64;
+        self.max_backoff_ms = max.as_millis() as u64;
+        self
+    }
+    pub fn jitter(mut self, jitter: bool) -> RetryPolicy {
+        self.jitter = jitter;
+        self
+    }
+    pub fn retry_on_status(mut self, statuses: Vec<u16>) -> RetryPolicy {
+        self.retry_on_status = statuses;
+        self
+    }
+
+    /// Delay before attempt `attempt + 1`, `attempt` starting at 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+        let ms = self
+            .initial_backoff_ms
+            .saturating_mul(factor)
+            .min(self.max_backoff_ms);
+        let ms = match self.jitter {
+            true => ms / 2 + (random_unit() * (ms - ms / 2) as f64) as u64,
+            false => ms,
+        };
+        Duration::from_millis(ms)
+    }
+
+    pub fn is_retryable_status(&self, status: u16) -> bool {
+        self.retry_on_status.contains(&status)
+    }
+
+    pub(crate) fn should_retry(&self, res: &Result<Response, Error>) -> bool {
+        match res {
+            Ok(v) => self.is_retryable_status(v.status_code().as_u16()),
+            Err(e) => self.retry_on_connection_error && is_connection_error(e),
+        }
+    }
+}
+
+pub(crate) fn is_connection_error(error: &Error) -> bool {
+    match std::error::Error::source(error).and_then(|v| v.downcast_ref::<reqwest::Error>()) {
+        Some(e) => e.is_connect() || e.is_timeout(),
+        None => false,
+    }
+}
+
+fn random_unit() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.subsec_nanos())
+        .unwrap_or_default();
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(nanos);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,264 @@
+use crate::config::redact;
+use crate::error::ElasticError;
+use elasticsearch::auth::ClientCertificate;
+use elasticsearch::cert::{Certificate, CertificateValidation};
+use elasticsearch::http::Url;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// TLS settings for `https` nodes.
+///
+/// ```toml
+/// [tls]
+/// ca_cert = "/etc/elasticsearch/certs/http_ca.crt"
+/// client_cert = "client.crt"
+/// client_key = "client.key"
+/// ```
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// PEM bundle of CA certificates trusted for the cluster.
+    pub ca_cert: Option<PathBuf>,
+    /// PEM client certificate (optionally followed by its chain) for mutual TLS.
+    pub client_cert: Option<PathBuf>,
+    /// PEM private key matching `client_cert`.
+    pub client_key: Option<PathBuf>,
+    /// PKCS#12 client identity, as an alternative to `client_cert`/`client_key`.
+    pub client_p12: Option<PathBuf>,
+    pub client_p12_password: Option<String>,
+    /// SHA-256 fingerprint of the cluster CA, as printed by the 8.x security
+    /// auto-configuration. Colons and case are ignored.
+    pub ca_fingerprint: Option<String>,
+    /// Skip certificate validation entirely. Development only.
+    pub insecure: bool,
+}
+
+/// The PKCS#12 password is printed as `"<redacted>"`.
+impl std::fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("ca_cert", &self.ca_cert)
+            .field("client_cert", &self.client_cert)
+            .field("client_key", &self.client_key)
+            .field("client_p12", &self.client_p12)
+            .field("client_p12_password", &redact(&self.client_p12_password))
+            .field("ca_fingerprint", &self.ca_fingerprint)
+            .field("insecure", &self.insecure)
+            .finish()
+    }
+}
+
+impl TlsConfig {
+    pub(crate) fn validation(
+        &self,
+        nodes: &[Url],
+    ) -> Result<Option<CertificateValidation>, ElasticError> {
+        if self.insecure {
+            return Ok(Some(CertificateValidation::None));
+        }
+        let mut pem = match &self.ca_cert {
+            Some(path) => read_file(path)?,
+            None => vec![],
+        };
+        if let Some(fingerprint) = &self.ca_fingerprint {
+            let fingerprint = normalize_fingerprint(fingerprint)?;
+            pem.extend(pinned_certificate(nodes, &fingerprint)?);
+        }
+        if pem.is_empty() {
+            return Ok(None);
+        }
+        let cert = Certificate::from_pem(&pem)
+            .map_err(|e| ElasticError::Config(format!("ca certificate: {}", e)))?;
+        Ok(Some(CertificateValidation::Full(cert)))
+    }
+
+    pub(crate) fn identity(&self) -> Result<Option<ClientCertificate>, ElasticError> {
+        if let Some(path) = &self.client_p12 {
+            if self.client_cert.is_some() {
+                return Err(ElasticError::Config(
+                    "client_p12 and client_cert cannot be used together".to_string(),
+                ));
+            }
+            return Ok(Some(ClientCertificate::Pkcs12(
+                read_file(path)?,
+                self.client_p12_password.clone(),
+            )));
+        }
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => Ok(Some(imp::pkcs12_from_pem(
+                &read_file(cert)?,
+                &read_file(key)?,
+            )?)),
+            (None, None) => Ok(None),
+            _ => Err(ElasticError::Config(
+                "client_cert and client_key must be set together".to_string(),
+            )),
+        }
+    }
+}
+
+/// Finds the pinned CA on the first reachable https node. The CA is then the
+/// trusted root of every connection, to sniffed nodes as well. The handshake
+/// blocks, so inside a multi-thread runtime it runs in `block_in_place`; a
+/// current-thread runtime is blocked unless the build runs in
+/// `spawn_blocking`, as `ClientBuilder::build_async` does.
+fn pinned_certificate(nodes: &[Url], fingerprint: &str) -> Result<Vec<u8>, ElasticError> {
+    let nodes: Vec<&Url> = nodes.iter().filter(|v| v.scheme() == "https").collect();
+    if nodes.is_empty() {
+        return Err(ElasticError::Config(
+            "ca_fingerprint requires an https node".to_string(),
+        ));
+    }
+    let probe = || {
+        let mut error = None;
+        for node in &nodes {
+            match imp::pinned_certificate(node, fingerprint) {
+                Ok(v) => return Ok(v),
+                // A node that is down does not fail the build while another
+                // one answers; a certificate mismatch does.
+                Err(e @ ElasticError::Connection(_)) => error = Some(e),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(error.unwrap_or_else(|| ElasticError::Connection("no https node".to_string())))
+    };
+    match tokio::runtime::Handle::try_current() {
+        Ok(v) if v.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(probe)
+        }
+        _ => probe(),
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, ElasticError> {
+    std::fs::read(path).map_err(|e| ElasticError::Config(format!("{}: {}", path.display(), e)))
+}
+
+fn normalize_fingerprint(value: &str) -> Result<String, ElasticError> {
+    let hex: String = value
+        .chars()
+        .filter(|c| *c != ':' && !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ElasticError::Config(format!(
+            "ca_fingerprint is not a SHA-256 hex digest: {}",
+            value
+        )));
+    }
+    Ok(hex)
+}
+
+#[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+mod imp {
+    use crate::error::ElasticError;
+    use elasticsearch::auth::ClientCertificate;
+    use elasticsearch::http::Url;
+    use openssl::hash::MessageDigest;
+    use openssl::pkcs12::Pkcs12;
+    use openssl::pkey::PKey;
+    use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+    use openssl::stack::Stack;
+    use openssl::x509::X509;
+    use std::net::{TcpStream, ToSocketAddrs};
+    use std::time::Duration;
+
+    fn tls_error<E: std::fmt::Display>(e: E) -> ElasticError {
+        ElasticError::Config(e.to_string())
+    }
+
+    /// Connects to `node` without validation and returns the PEM of the
+    /// certificate in the presented chain whose SHA-256 matches `fingerprint`.
+    /// That certificate is then trusted as a root, so the real connections
+    /// still go through full chain and hostname validation.
+    pub(super) fn pinned_certificate(
+        url: &Url,
+        fingerprint: &str,
+    ) -> Result<Vec<u8>, ElasticError> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| ElasticError::Config(format!("no host in {}", url)))?;
+        let port = url.port_or_known_default().unwrap_or(9200);
+        let addr = (host, port)
+            .to_socket_addrs()
+            .map_err(|e| ElasticError::Connection(format!("{}: {}", url, e)))?
+            .next()
+            .ok_or_else(|| ElasticError::Connection(format!("{}: no address", url)))?;
+        let stream = TcpStream::connect_timeout(&addr, Duration::from_secs(10))
+            .map_err(|e| ElasticError::Connection(format!("{}: {}", url, e)))?;
+
+        let mut builder = SslConnector::builder(SslMethod::tls()).map_err(tls_error)?;
+        builder.set_verify(SslVerifyMode::NONE);
+        let stream = builder
+            .build()
+            .configure()
+            .map_err(tls_error)?
+            .verify_hostname(false)
+            .connect(host, stream)
+            .map_err(|e| ElasticError::Connection(format!("{}: {}", url, e)))?;
+
+        if let Some(chain) = stream.ssl().peer_cert_chain() {
+            for cert in chain {
+                let digest = cert.digest(MessageDigest::sha256()).map_err(tls_error)?;
+                let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+                if hex == fingerprint {
+                    return cert.to_pem().map_err(tls_error);
+                }
+            }
+        }
+        Err(ElasticError::Config(format!(
+            "{} did not present a certificate matching ca_fingerprint",
+            url
+        )))
+    }
+
+    pub(super) fn pkcs12_from_pem(
+        cert: &[u8],
+        key: &[u8],
+    ) -> Result<ClientCertificate, ElasticError> {
+        let mut certs = X509::stack_from_pem(cert).map_err(tls_error)?;
+        if certs.is_empty() {
+            return Err(ElasticError::Config(
+                "client_cert has no certificate".to_string(),
+            ));
+        }
+        let leaf = certs.remove(0);
+        let key = PKey::private_key_from_pem(key).map_err(tls_error)?;
+        let mut chain = Stack::new().map_err(tls_error)?;
+        for cert in certs {
+            chain.push(cert).map_err(tls_error)?;
+        }
+        // native-tls only accepts PKCS#12 identities, so the PEM pair is
+        // repacked with a throwaway password.
+        let password = "uiuifree-elastic";
+        let der = Pkcs12::builder()
+            .name("client")
+            .pkey(&key)
+            .cert(&leaf)
+            .ca(chain)
+            .build2(password)
+            .and_then(|v| v.to_der())
+            .map_err(tls_error)?;
+        Ok(ClientCertificate::Pkcs12(der, Some(password.to_string())))
+    }
+}
+
+#[cfg(any(target_os = "windows", target_vendor = "apple"))]
+mod imp {
+    use crate::error::ElasticError;
+    use elasticsearch::auth::ClientCertificate;
+    use elasticsearch::http::Url;
+
+    pub(super) fn pinned_certificate(_: &Url, _: &str) -> Result<Vec<u8>, ElasticError> {
+        Err(ElasticError::Config(
+            "ca_fingerprint is only supported with the OpenSSL backend".to_string(),
+        ))
+    }
+
+    pub(super) fn pkcs12_from_pem(_: &[u8], _: &[u8]) -> Result<ClientCertificate, ElasticError> {
+        Err(ElasticError::Config(
+            "PEM client certificates are only supported with the OpenSSL backend, use client_p12"
+                .to_string(),
+        ))
+    }
+}
@@ -0,0 +1,389 @@
+use crate::bulk::{BulkFailure, BulkIngesterStats, BulkOperation};
+use crate::error::ElasticError;
+use crate::paging::page_body;
+use crate::{parse_response, IndicesApi};
+use elastic_query_builder::QueryBuilder;
+use elasticsearch::indices::IndicesGetParts;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// Options of [`IndicesApi::export_index_with`].
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    /// `_source` fields to keep; all when empty.
+    pub source_includes: Vec<String>,
+    pub source_excludes: Vec<String>,
+    pub gzip: bool,
+    /// Documents per search page.
+    pub page_size: i64,
+    /// How long the point in time is kept alive between pages.
+    pub keep_alive: String,
+    /// Writes the index's [`IndexHeader`] as a first `{"_header": ...}`
+    /// line, for [`ImportOptions::create_index`].
+    pub header: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            source_includes: vec![],
+            source_excludes: vec![],
+            gzip: false,
+            page_size: 1000,
+            keep_alive: "1m".to_string(),
+            header: false,
+        }
+    }
+}
+
+/// Options of [`IndicesApi::import_index_with`].
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub gzip: bool,
+    /// Documents per bulk request.
+    pub max_actions: usize,
+    /// Bulk request size that triggers a flush.
+    pub max_bytes: usize,
+    pub refresh: bool,
+    /// Creates the index from the header line of the file before importing.
+    /// Without a header line the import fails. A header line is skipped
+    /// otherwise.
+    pub create_index: bool,
+    /// Also gives the created index the aliases of the header. Off by
+    /// default: in the cluster the export came from, the aliases already
+    /// exist, and a write alias would move to the new index.
+    pub aliases: bool,
+    /// Failed documents kept for [`ImportFailure::failures`]; the others
+    /// are only counted.
+    pub max_failures: usize,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            gzip: false,
+            max_actions: 1000,
+            max_bytes: 5 * 1024 * 1024,
+            refresh: false,
+            create_index: false,
+            aliases: false,
+            max_failures: 100,
+        }
+    }
+}
+
+/// Mappings, settings and aliases of an index, to recreate it elsewhere
+/// with [`IndicesApi::create_from_header`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IndexHeader {
+    #[serde(default)]
+    pub mappings: Value,
+    #[serde(default)]
+    pub settings: Value,
+    #[serde(default)]
+    pub aliases: Value,
+}
+
+/// Outcome of an import in which some documents could not be written,
+/// returned as [`ElasticError::ImportFailure`].
+#[derive(Debug, Clone)]
+pub struct ImportFailure {
+    /// Counts of the whole import; `failed` counts every failed document.
+    pub stats: BulkIngesterStats,
+    /// The first [`ImportOptions::max_failures`] failed documents.
+    pub failures: Vec<BulkFailure>,
+}
+
+/// Settings Elasticsearch sets itself, rejected when creating an index.
+const PRIVATE_SETTINGS: [&str; 4] = ["uuid", "creation_date", "provided_name", "version"];
+
+/// One exported document, a line of the NDJSON file.
+#[derive(Serialize, Deserialize)]
+struct Line {
+    _id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    _routing: Option<String>,
+    #[serde(default)]
+    _source: Value,
+}
+
+impl IndicesApi {
+    /// Writes every document of `index` to `writer` as NDJSON, one
+    /// `{"_id", "_routing", "_source"}` object per line, and returns the
+    /// number of documents written.
+    ///
+    /// The writer is written synchronously between pages; pass a file or a
+    /// buffer.
+    ///
+    /// ```no_run
+    /// # async fn run(api: uiuifree_elastic::ElasticApi) {
+    /// use uiuifree_elastic::{ExportOptions, ImportOptions};
+    ///
+    /// let file = std::fs::File::create("users.ndjson").unwrap();
+    /// api.indices().export_index("users", file).await.unwrap();
+    /// let file = std::fs::File::open("users.ndjson").unwrap();
+    /// api.indices().import_index("users_copy", file).await.unwrap();
+    ///
+    /// // With mappings and settings, into an index that does not exist yet.
+    /// let file = std::fs::File::create("users.ndjson").unwrap();
+    /// let export = ExportOptions { header: true, ..Default::default() };
+    /// api.indices().export_index_with("users", file, &export).await.unwrap();
+    /// let file = std::fs::File::open("users.ndjson").unwrap();
+    /// let import = ImportOptions { create_index: true, ..Default::default() };
+    /// api.indices().import_index_with("users_copy", file, &import).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn export_index<W: Write + Send>(
+        &self,
+        index: &str,
+        writer: W,
+    ) -> Result<u64, ElasticError> {
+        self.export_index_with(index, writer, &ExportOptions::default())
+            .await
+    }
+
+    pub async fn export_index_with<W: Write + Send>(
+        &self,
+        index: &str,
+        writer: W,
+        options: &ExportOptions,
+    ) -> Result<u64, ElasticError> {
+        let mut query = QueryBuilder::new();
+        query.set_size(options.page_size);
+        let mut body = page_body(&query);
+        if !options.source_includes.is_empty() || !options.source_excludes.is_empty() {
+            body["_source"] = json!({
+                "includes": options.source_includes,
+                "excludes": options.source_excludes,
+            });
+        }
+        let mut lines =
+            self.api
+                .search()
+                .search_after_body::<Line>(index, body, &options.keep_alive);
+
+        let mut output = match options.gzip {
+            true => Output::Gzip(GzEncoder::new(writer, Compression::default())),
+            false => Output::Plain(writer),
+        };
+        if options.header {
+            let header = json!({ "_header": self.header(index).await? });
+            writeln!(output, "{}", header).map_err(io_error)?;
+        }
+        let mut count = 0;
+        while let Some(line) = lines.try_next().await? {
+            let line =
+                serde_json::to_string(&line).map_err(|e| ElasticError::JsonParse(e.to_string()))?;
+            writeln!(output, "{}", line).map_err(io_error)?;
+            count += 1;
+        }
+        output.finish()?;
+        Ok(count)
+    }
+
+    /// Indexes the documents of an [`export_index`](IndicesApi::export_index)
+    /// file into `index` through a [`BulkIngester`](crate::BulkIngester),
+    /// keeping their ids and routing. Memory stays bounded by one bulk
+    /// request per concurrent request.
+    ///
+    /// The reader is read on a blocking thread. When documents could not be
+    /// written, an [`ElasticError::ImportFailure`] with the first of them is
+    /// returned once the whole file was read.
+    pub async fn import_index<R: Read + Send + 'static>(
+        &self,
+        index: &str,
+        reader: R,
+    ) -> Result<BulkIngesterStats, ElasticError> {
+        self.import_index_with(index, reader, &ImportOptions::default())
+            .await
+    }
+
+    pub async fn import_index_with<R: Read + Send + 'static>(
+        &self,
+        index: &str,
+        reader: R,
+        options: &ImportOptions,
+    ) -> Result<BulkIngesterStats, ElasticError> {
+        let (sender, mut lines) = mpsc::channel(options.max_actions.max(1));
+        let gzip = options.gzip;
+        let reading = tokio::task::spawn_blocking(move || {
+            let reader: Box<dyn BufRead> = match gzip {
+                true => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+                false => Box::new(BufReader::new(reader)),
+            };
+            for (number, line) in reader.lines().enumerate() {
+                let line = line.map(|v| (number + 1, v)).map_err(io_error);
+                let stop = line.is_err();
+                // Fails once the import stopped and dropped the receiver.
+                if sender.blocking_send(line).is_err() || stop {
+                    break;
+                }
+            }
+        });
+        let failures = Arc::new(Mutex::new(Vec::<BulkFailure>::new()));
+        let ingester = self
+            .api
+            .bulk()
+            .ingester()
+            .max_actions(options.max_actions)
+            .max_bytes(options.max_bytes)
+            .refresh(options.refresh)
+            .on_failure({
+                let failures = failures.clone();
+                let max_failures = options.max_failures;
+                move |failure| {
+                    let mut failures = failures.lock().unwrap();
+                    if failures.len() < max_failures {
+                        failures.push(failure);
+                    }
+                }
+            })
+            .build();
+
+        let read = async {
+            let mut first = true;
+            while let Some(line) = lines.recv().await {
+                let (number, line) = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let parse_error = |e| ElasticError::JsonParse(format!("line {}: {}", number, e));
+                let value: Value = serde_json::from_str(&line).map_err(parse_error)?;
+                if std::mem::take(&mut first) {
+                    if let Some(header) = value.get("_header") {
+                        if options.create_index {
+                            let header: IndexHeader =
+                                serde_json::from_value(header.clone()).map_err(parse_error)?;
+                            self.create_from_header(index, &header, options.aliases)
+                                .await?;
+                        }
+                        continue;
+                    }
+                    if options.create_index {
+                        return Err(missing_header());
+                    }
+                }
+                let line: Line = serde_json::from_value(value).map_err(parse_error)?;
+                let mut operation = BulkOperation::index(index, &line._source).id(&line._id);
+                if let Some(routing) = &line._routing {
+                    operation = operation.routing(routing);
+                }
+                ingester.add_operation(operation).await?;
+            }
+            if first && options.create_index {
+                return Err(missing_header());
+            }
+            Ok::<(), ElasticError>(())
+        }
+        .await;
+        drop(lines);
+        let stats = ingester.close().await?;
+        read?;
+        reading.await.map_err(|e| ElasticError::Io(e.to_string()))?;
+
+        if stats.failed > 0 {
+            let failures = std::mem::take(&mut *failures.lock().unwrap());
+            return Err(ElasticError::ImportFailure(Box::new(ImportFailure {
+                stats,
+                failures,
+            })));
+        }
+        Ok(stats)
+    }
+
+    /// Mappings, settings and aliases of `index`, without the settings
+    /// Elasticsearch sets itself.
+    pub async fn header(&self, index: &str) -> Result<IndexHeader, ElasticError> {
+        let res = self
+            .api
+            .send_idempotent(&self.retry, |client| async move {
+                client
+                    .indices()
+                    .get(IndicesGetParts::Index(&[index]))
+                    .send()
+                    .await
+            })
+            .await;
+        let res: Value = parse_response(res).await?;
+        // Keyed by the concrete index, which differs for an alias.
+        let mut header: IndexHeader = match res.as_object().and_then(|v| v.values().next()) {
+            Some(v) => serde_json::from_value(v.clone())
+                .map_err(|e| ElasticError::JsonParse(e.to_string()))?,
+            None => return Err(ElasticError::NotFound(index.to_string())),
+        };
+        if let Some(settings) = header.settings["index"].as_object_mut() {
+            for key in PRIVATE_SETTINGS {
+                settings.remove(key);
+            }
+        }
+        Ok(header)
+    }
+
+    /// Creates `index` with the mappings and settings of `header`, and its
+    /// aliases when `aliases` is set. Leave it unset to copy an index within
+    /// one cluster: the aliases exist there already.
+    pub async fn create_from_header(
+        &self,
+        index: &str,
+        header: &IndexHeader,
+        aliases: bool,
+    ) -> Result<bool, ElasticError> {
+        let mut body = json!({});
+        for (key, value) in [
+            ("mappings", &header.mappings),
+            ("settings", &header.settings),
+            ("aliases", &header.aliases),
+        ] {
+            if !value.is_null() && (aliases || key != "aliases") {
+                body[key] = value.clone();
+            }
+        }
+        self.create(index, body).await
+    }
+}
+
+enum Output<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+}
+
+impl<W: Write> Output<W> {
+    fn finish(self) -> Result<(), ElasticError> {
+        match self {
+            Output::Plain(mut v) => v.flush(),
+            Output::Gzip(v) => v.finish().and_then(|mut v| v.flush()),
+        }
+        .map_err(io_error)
+    }
+}
+
+impl<W: Write> Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Plain(v) => v.write(buf),
+            Output::Gzip(v) => v.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Plain(v) => v.flush(),
+            Output::Gzip(v) => v.flush(),
+        }
+    }
+}
+
+fn missing_header() -> ElasticError {
+    ElasticError::Config("create_index needs an export written with a header".to_string())
+}
+
+fn io_error(error: std::io::Error) -> ElasticError {
+    ElasticError::Io(error.to_string())
+}
@@ -0,0 +1,366 @@
+mod common;
+
+use common::{StubRequest, StubResponse, StubServer};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use uiuifree_elastic::error::ElasticError;
+use uiuifree_elastic::{BulkIngesterStats, BulkOperation, ClientBuilder, RetryPolicy};
+
+fn lines(request: &StubRequest) -> Vec<Value> {
+    request
+        .body
+        .lines()
+        .map(|v| serde_json::from_str(v).unwrap())
+        .collect()
+}
+
+/// Answers each action with the status `status` picks for its `_id`.
+fn bulk_server<F>(status: F) -> StubServer
+where
+    F: Fn(&str) -> u16 + Send + Sync + 'static,
+{
+    StubServer::start(move |req| {
+        let mut items = vec![];
+        let lines = lines(req);
+        let mut i = 0;
+        while i < lines.len() {
+            let (action, meta) = lines[i].as_object().unwrap().iter().next().unwrap();
+            let id = meta["_id"].as_str().unwrap_or("");
+            let status = status(id);
+            let mut item = json!({"_index": "logs", "_id": id, "status": status});
+            match status {
+                400 => item["error"] = json!({"type": "mapper_parsing_exception", "reason": "bad"}),
+                429 => {
+                    item["error"] =
+                        json!({"type": "es_rejected_execution_exception", "reason": "busy"})
+                }
+                503 => {
+                    item["error"] =
+                        json!({"type": "unavailable_shards_exception", "reason": "down"})
+                }
+                _ => {}
+            }
+            items.push(json!({ action.as_str(): item }));
+            i += if action == "delete" { 1 } else { 2 };
+        }
+        let errors = items.iter().any(|v| v.to_string().contains("error"));
+        StubResponse::json(
+            200,
+            &json!({"took": 1, "errors": errors, "items": items}).to_string(),
+        )
+    })
+}
+
+fn action(id: usize) -> Value {
+    json!({"index": {"_index": "logs", "_id": id.to_string()}})
+}
+
+#[tokio::test]
+pub async fn case01() {
+    // flush by count, and on close
+    let server = bulk_server(|_| 201);
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    let ingester = api.bulk().ingester().max_actions(2).build();
+    for i in 0..5 {
+        ingester
+            .add(action(i), Some(json!({"n": i})))
+            .await
+            .unwrap();
+    }
+    ingester
+        .add(json!({"delete": {"_index": "logs", "_id": "9"}}), None)
+        .await
+        .unwrap();
+    let stats = ingester.close().await.unwrap();
+    assert_eq!(
+        stats,
+        BulkIngesterStats {
+            requests: 3,
+            succeeded: 6,
+            retried: 0,
+            failed: 0
+        }
+    );
+    let sizes: Vec<_> = server.requests().iter().map(|v| lines(v).len()).collect();
+    assert_eq!(sizes, [4, 4, 3]);
+}
+
+#[tokio::test]
+pub async fn case02() {
+    // only rejected items are retried; the rest are reported
+    let rejected = Arc::new(AtomicUsize::new(0));
+    let server = {
+        let rejected = rejected.clone();
+        bulk_server(move |id| match id {
+            "1" if rejected.fetch_add(1, Ordering::SeqCst) == 0 => 429,
+            "2" => 400,
+            _ => 201,
+        })
+    };
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    let (tx, mut failures) = tokio::sync::mpsc::unbounded_channel();
+    let ingester = api
+        .bulk()
+        .ingester()
+        .retry_policy(
+            RetryPolicy::new(3).backoff(Duration::from_millis(1), Duration::from_millis(5)),
+        )
+        .failure_channel(tx)
+        .build();
+    for i in 0..3 {
+        ingester
+            .add(action(i), Some(json!({"n": i})))
+            .await
+            .unwrap();
+    }
+    ingester.flush().await.unwrap();
+    assert_eq!(ingester.stats().succeeded, 2);
+
+    let stats = ingester.close().await.unwrap();
+    assert_eq!((stats.requests, stats.retried, stats.failed), (2, 1, 1));
+    let requests = server.requests();
+    assert_eq!(lines(&requests[1]), [action(1), json!({"n": 1})]);
+
+    let failure = failures.recv().await.unwrap();
+    assert_eq!(failure.action, action(2));
+    assert_eq!(failure.source, Some(json!({"n": 2})));
+    assert_eq!(failure.status, Some(400));
+    assert!(failure.error.is_mapping_error());
+    assert!(failures.recv().await.is_none());
+}
+
+#[tokio::test]
+pub async fn case03() {
+    // flush by bytes and by interval
+    let server = bulk_server(|_| 201);
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    let ingester = api.bulk().ingester().max_bytes(10).build();
+    ingester
+        .add(action(1), Some(json!({"n": 1})))
+        .await
+        .unwrap();
+    ingester
+        .add(action(2), Some(json!({"n": 2})))
+        .await
+        .unwrap();
+    ingester.flush().await.unwrap();
+    assert_eq!(server.requests().len(), 2);
+
+    let ingester = api
+        .bulk()
+        .ingester()
+        .flush_interval(Duration::from_millis(50))
+        .build();
+    ingester
+        .add(action(3), Some(json!({"n": 3})))
+        .await
+        .unwrap();
+    for _ in 0..50 {
+        if server.requests().len() == 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(server.requests().len(), 3);
+    assert_eq!(ingester.close().await.unwrap().succeeded, 1);
+}
+
+#[tokio::test]
+pub async fn case04() {
+    // typed operations
+    let server = bulk_server(|_| 200);
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    let operations = [
+        BulkOperation::index("logs", &json!({"n": 1}))
+            .id("1")
+            .pipeline("enrich")
+            .require_alias(true),
+        BulkOperation::create("logs", "2", &json!({"n": 2})).routing("r"),
+        BulkOperation::update_doc("logs", "3", &json!({"n": 3}))
+            .if_seq_no(7, 1)
+            .doc_as_upsert(true),
+        BulkOperation::update_script("logs", "4", json!({"source": "ctx._source.n++"}))
+            .upsert(&json!({"n": 0}))
+            .retry_on_conflict(2),
+        BulkOperation::delete("logs", "5").version(9, "external"),
+    ];
+    api.bulk().operations(&operations, false).await.unwrap();
+    let request = server.requests().pop().unwrap();
+    assert_eq!(
+        lines(&request),
+        [
+            json!({"index": {"_index": "logs", "_id": "1", "pipeline": "enrich", "require_alias": true}}),
+            json!({"n": 1}),
+            json!({"create": {"_index": "logs", "_id": "2", "routing": "r"}}),
+            json!({"n": 2}),
+            json!({"update": {"_index": "logs", "_id": "3", "if_seq_no": 7, "if_primary_term": 1}}),
+            json!({"doc": {"n": 3}, "doc_as_upsert": true}),
+            json!({"update": {"_index": "logs", "_id": "4", "retry_on_conflict": 2}}),
+            json!({"script": {"source": "ctx._source.n++"}, "upsert": {"n": 0}}),
+            json!({"delete": {"_index": "logs", "_id": "5", "version": 9, "version_type": "external"}}),
+        ]
+    );
+    assert!(request.body.ends_with('\n'));
+
+    let ingester = api.bulk().ingester().build();
+    ingester
+        .add_operation(BulkOperation::delete("logs", "6"))
+        .await
+        .unwrap();
+    assert_eq!(ingester.close().await.unwrap().succeeded, 1);
+    let request = server.requests().pop().unwrap();
+    assert_eq!(
+        lines(&request),
+        [json!({"delete": {"_index": "logs", "_id": "6"}})]
+    );
+}
+
+#[tokio::test]
+pub async fn case05() {
+    // typed response and partial failures
+    let server = bulk_server(|id| match id {
+        "2" => 400,
+        "3" => 429,
+        _ => 201,
+    });
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    let operations = [
+        BulkOperation::delete("logs", "1"),
+        BulkOperation::index("logs", &json!({"n": 2})).id("2"),
+        BulkOperation::create("logs", "3", &json!({"n": 3})),
+        BulkOperation::index("logs", &json!({"n": 4})).id("4"),
+    ];
+    let res = api.bulk().operations(&operations, false).await.unwrap();
+    assert!(res.errors);
+    assert_eq!(res.items.len(), 4);
+    assert_eq!(res.items[0].action, "delete");
+    assert_eq!(res.items[2].action, "create");
+    assert_eq!(res.successful_ids(), ["1", "4"]);
+    let failed = res.failed_items();
+    assert_eq!(failed.len(), 2);
+    assert!(failed[0].error.as_ref().unwrap().is_mapping_error());
+    assert!(failed[1].error.as_ref().unwrap().is_retryable());
+    let failed = res.failed_operations(&operations);
+    assert_eq!(failed[0].0, &operations[1]);
+    assert_eq!(failed[1].1.status, 429);
+
+    let err = api
+        .bulk()
+        .fail_on_item_errors(true)
+        .operations(&operations, false)
+        .await
+        .unwrap_err();
+    let failures = match err {
+        ElasticError::BulkPartialFailure(v) => v,
+        e => panic!("{:?}", e),
+    };
+    assert_eq!(failures.len(), 2);
+    assert_eq!(failures[0].action, operations[1].action());
+    assert_eq!(failures[0].source, Some(json!({"n": 2})));
+    assert_eq!(failures[1].action, operations[2].action());
+    assert_eq!(failures[1].status, Some(429));
+
+    let server = bulk_server(|_| 201);
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    let res = api
+        .bulk()
+        .fail_on_item_errors(true)
+        .operations(&operations, false)
+        .await
+        .unwrap();
+    assert_eq!(res.successful_ids(), ["1", "2", "3", "4"]);
+}
+
+#[tokio::test]
+pub async fn case06() {
+    // only rejected items are sent again
+    let calls = Arc::new(AtomicUsize::new(0));
+    let server = bulk_server({
+        let calls = calls.clone();
+        move |id| match id {
+            "2" if calls.fetch_add(1, Ordering::SeqCst) == 0 => 429,
+            "3" => 503,
+            "4" => 400,
+            _ => 201,
+        }
+    });
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    let operations = [
+        BulkOperation::index("logs", &json!({"n": 1})),
+        BulkOperation::index("logs", &json!({"n": 2})).id("2"),
+        BulkOperation::index("logs", &json!({"n": 3})).id("3"),
+        BulkOperation::index("logs", &json!({"n": 4})).id("4"),
+    ];
+    let policy = RetryPolicy::new(3).backoff(Duration::from_millis(1), Duration::from_millis(1));
+    let report = api
+        .bulk()
+        .with_retry(policy.clone())
+        .operations_with_retry(&operations, false)
+        .await
+        .unwrap();
+    assert!(!report.is_success());
+    assert_eq!(report.requests, 3);
+    assert_eq!(report.succeeded.len(), 1);
+    assert_eq!(report.retried[0].id.as_deref(), Some("2"));
+    assert_eq!(report.failed.len(), 2);
+    assert_eq!(report.failed[0].action, operations[3].action());
+    assert!(report.failed[0].error.is_mapping_error());
+    assert_eq!(report.failed[1].action, operations[2].action());
+    assert_eq!(report.failed[1].status, Some(503));
+
+    let requests = server.requests();
+    assert_eq!(lines(&requests[0]).len(), 8);
+    assert_eq!(
+        lines(&requests[1]),
+        operations[1..3]
+            .iter()
+            .flat_map(|v| v.lines())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(lines(&requests[2]), operations[2].lines());
+
+    let err = api
+        .bulk()
+        .with_retry(policy)
+        .fail_on_item_errors(true)
+        .operations_with_retry(&operations[2..3], false)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ElasticError::BulkPartialFailure(v) if v.len() == 1));
+}
+
+#[tokio::test]
+pub async fn case07() {
+    // a gateway error resends the whole request only when it is safe to repeat
+    let server = StubServer::start(|_| StubResponse::json(502, "{}"));
+    let policy = RetryPolicy::new(3).backoff(Duration::from_millis(1), Duration::from_millis(1));
+    let api = ClientBuilder::new()
+        .node(&server.url)
+        .retry_policy(policy.clone())
+        .build()
+        .unwrap();
+
+    let auto_id = [BulkOperation::index("logs", &json!({"n": 1}))];
+    let report = api
+        .bulk()
+        .with_retry(policy.clone())
+        .operations_with_retry(&auto_id, false)
+        .await
+        .unwrap();
+    assert_eq!(report.requests, 1);
+    assert_eq!(report.failed[0].status, Some(502));
+    assert_eq!(server.requests().len(), 1);
+
+    let with_id = [BulkOperation::index("logs", &json!({"n": 1})).id("1")];
+    let report = api
+        .bulk()
+        .with_retry(policy)
+        .operations_with_retry(&with_id, false)
+        .await
+        .unwrap();
+    assert_eq!(report.requests, 3);
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(server.requests().len(), 4);
+}
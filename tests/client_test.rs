@@ -0,0 +1,34 @@
+mod common;
+
+use common::{StubResponse, StubServer};
+use serde_json::json;
+use uiuifree_elastic::{ClientBuilder, ElasticApi};
+
+fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+pub async fn case01() {
+    // one client shared by many tasks
+    assert_shareable::<ElasticApi>();
+    let server = StubServer::start(|_| StubResponse::json(201, r#"{"result":"created"}"#));
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    let tasks: Vec<_> = (0..64)
+        .map(|i| {
+            let api = api.clone();
+            tokio::spawn(async move {
+                api.index()
+                    .doc("test", &i.to_string(), json!({ "i": i }), false)
+                    .await
+            })
+        })
+        .collect();
+    for task in tasks {
+        assert!(task.await.unwrap().is_ok());
+    }
+
+    let mut paths: Vec<String> = server.requests().into_iter().map(|v| v.path).collect();
+    paths.sort();
+    paths.dedup();
+    assert_eq!(paths.len(), 64);
+}
@@ -0,0 +1,151 @@
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[derive(Debug, Clone)]
+pub struct StubRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl StubRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+pub struct StubResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl StubResponse {
+    pub fn json(status: u16, body: &str) -> StubResponse {
+        StubResponse {
+            status,
+            body: body.to_string(),
+        }
+    }
+}
+
+type Handler = dyn Fn(&StubRequest) -> StubResponse + Send + Sync;
+
+/// Minimal HTTP/1.1 server answering every request through `handler`.
+pub struct StubServer {
+    pub url: String,
+    pub requests: Arc<Mutex<Vec<StubRequest>>>,
+}
+
+impl StubServer {
+    pub fn start<F>(handler: F) -> StubServer
+    where
+        F: Fn(&StubRequest) -> StubResponse + Send + Sync + 'static,
+    {
+        StubServer::listen("http", handler, |stream, handler, requests| {
+            serve(stream, handler, requests)
+        })
+    }
+
+    pub fn listen<F, S>(scheme: &str, handler: F, serve: S) -> StubServer
+    where
+        F: Fn(&StubRequest) -> StubResponse + Send + Sync + 'static,
+        S: Fn(TcpStream, Arc<Handler>, Arc<Mutex<Vec<StubRequest>>>) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "{}://localhost:{}",
+            scheme,
+            listener.local_addr().unwrap().port()
+        );
+        let requests = Arc::new(Mutex::new(vec![]));
+        let handler: Arc<Handler> = Arc::new(handler);
+        let serve = Arc::new(serve);
+        let shared = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                };
+                let handler = handler.clone();
+                let requests = shared.clone();
+                let serve = serve.clone();
+                thread::spawn(move || serve(stream, handler, requests));
+            }
+        });
+        StubServer { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<StubRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+pub fn serve<S: Read + Write>(
+    stream: S,
+    handler: Arc<Handler>,
+    requests: Arc<Mutex<Vec<StubRequest>>>,
+) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or_default().to_string();
+        let mut headers = vec![];
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((k, v)) = line.split_once(':') {
+                headers.push((k.trim().to_string(), v.trim().to_string()));
+            }
+        }
+        let length = headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+            .map(|(_, v)| v.parse::<usize>().unwrap_or(0))
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        let request = StubRequest {
+            method,
+            path,
+            headers,
+            body: String::from_utf8_lossy(&body).to_string(),
+        };
+        let response = handler(&request);
+        let head_only = request.method == "HEAD";
+        requests.lock().unwrap().push(request);
+        let head = format!(
+            "HTTP/1.1 {} Stub\r\ncontent-type: application/json\r\nx-elastic-product: Elasticsearch\r\ncontent-length: {}\r\n\r\n",
+            response.status,
+            response.body.len()
+        );
+        let stream = reader.get_mut();
+        if stream.write_all(head.as_bytes()).is_err()
+            || (!head_only && stream.write_all(response.body.as_bytes()).is_err())
+            || stream.flush().is_err()
+        {
+            return;
+        }
+    }
+}
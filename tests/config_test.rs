@@ -0,0 +1,70 @@
+use uiuifree_elastic::error::ElasticError;
+use uiuifree_elastic::{el_single_node, ClientBuilder, ElasticConfig};
+
+#[test]
+pub fn case01() {
+    let config = ElasticConfig::from_toml_str(
+        r#"
+nodes = ["http://es01:9200", "http://es02:9200"]
+username = "elastic"
+password = "changeme"
+timeout_ms = 1500
+
+[headers]
+x-opaque-id = "test"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.nodes.len(), 2);
+    assert_eq!(config.username.as_deref(), Some("elastic"));
+    assert_eq!(config.timeout_ms, Some(1500));
+    assert_eq!(config.headers.get("x-opaque-id").unwrap(), "test");
+    assert!(config.build().is_ok());
+}
+
+#[test]
+pub fn case02() {
+    let res = ClientBuilder::new().node("not a url").build();
+    assert!(matches!(res, Err(ElasticError::Config(_))));
+    assert!(matches!(
+        el_single_node("not a url"),
+        Err(ElasticError::Config(_))
+    ));
+
+    let res = ClientBuilder::new()
+        .node("http://localhost:9200")
+        .basic_auth("elastic", "changeme")
+        .bearer_token("token")
+        .build();
+    assert!(matches!(res, Err(ElasticError::Config(_))));
+
+    let res = ClientBuilder::new()
+        .node("http://localhost:9200")
+        .header("bad header", "value")
+        .build();
+    assert!(matches!(res, Err(ElasticError::Config(_))));
+
+    let res = ClientBuilder::new()
+        .nodes(["http://es01:9200", "http://es02:9200"])
+        .encoded_api_key("aWQ6a2V5")
+        .proxy("http://proxy:3128")
+        .build();
+    assert!(res.is_ok());
+}
+
+#[test]
+pub fn case03() {
+    // secrets stay out of Debug output
+    let builder = ClientBuilder::new()
+        .basic_auth("elastic", "s3cret-password")
+        .bearer_token("s3cret-token")
+        .proxy_auth("proxy", "s3cret-proxy")
+        .client_p12("client.p12", Some("s3cret-p12"));
+    let mut config = builder.config().clone();
+    config.api_key = Some("s3cret-key".to_string());
+    for debug in [format!("{:?}", builder), format!("{:?}", config)] {
+        assert!(!debug.contains("s3cret"), "{}", debug);
+        assert!(debug.contains("elastic"));
+        assert!(debug.contains("<redacted>"));
+    }
+}
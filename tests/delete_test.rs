@@ -0,0 +1,67 @@
+mod common;
+
+use common::{StubResponse, StubServer};
+use uiuifree_elastic::ClientBuilder;
+
+#[tokio::test]
+pub async fn case01() {
+    let server = StubServer::start(|req| {
+        match (req.method.as_str(), req.path.split('?').next().unwrap()) {
+            ("DELETE", "/users/_doc/1") => StubResponse::json(
+                200,
+                r#"{"_index":"users","_id":"1","_version":4,"_seq_no":9,"_primary_term":2,"result":"deleted",
+                    "_shards":{"total":2,"successful":1,"failed":0}}"#,
+            ),
+            ("DELETE", "/users/_doc/2") => StubResponse::json(
+                404,
+                r#"{"_index":"users","_id":"2","_version":1,"result":"not_found"}"#,
+            ),
+            ("DELETE", "/users/_doc/3") => StubResponse::json(
+                409,
+                r#"{"error":{"type":"version_conflict_engine_exception","reason":"[3]: version conflict"},"status":409}"#,
+            ),
+            _ => StubResponse::json(
+                404,
+                r#"{"error":{"type":"index_not_found_exception","reason":"no such index [gone]"},"status":404}"#,
+            ),
+        }
+    });
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    let res = api
+        .delete()
+        .routing("a")
+        .refresh(true)
+        .if_seq_no(8, 2)
+        .doc("users", "1")
+        .await
+        .unwrap();
+    assert!(res.is_deleted());
+    assert_eq!(res._version, Some(4));
+    assert_eq!(res._seq_no, Some(9));
+    assert_eq!(res._primary_term, Some(2));
+    let path = &server.requests()[0].path;
+    for param in [
+        "routing=a",
+        "refresh=true",
+        "if_seq_no=8",
+        "if_primary_term=2",
+    ] {
+        assert!(path.contains(param), "{} in {}", param, path);
+    }
+
+    let res = api.delete().doc("users", "2").await.unwrap();
+    assert!(res.is_not_found());
+    assert!(!res.is_deleted());
+
+    let err = api
+        .delete()
+        .if_seq_no(1, 1)
+        .doc("users", "3")
+        .await
+        .unwrap_err();
+    assert!(err.is_version_conflict());
+
+    let err = api.delete().doc("gone", "1").await.unwrap_err();
+    assert!(err.is_index_not_found());
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use common::{StubResponse, StubServer};
+use serde_json::json;
+use uiuifree_elastic::error::ElasticError;
+use uiuifree_elastic::ClientBuilder;
+
+const MAPPING_ERROR: &str = r#"{
+  "error": {
+    "root_cause": [{"type": "document_parsing_exception", "reason": "[1:42] failed to parse field [created_at] of type [date]"}],
+    "type": "document_parsing_exception",
+    "reason": "[1:42] failed to parse field [created_at] of type [date]",
+    "caused_by": {
+      "type": "illegal_argument_exception",
+      "reason": "failed to parse date field [2020-01-01 00:00:00]"
+    }
+  },
+  "status": 400
+}"#;
+
+const VERSION_CONFLICT: &str = r#"{
+  "error": {
+    "root_cause": [{"type": "version_conflict_engine_exception", "reason": "[1]: version conflict", "index": "test", "shard": "0"}],
+    "type": "version_conflict_engine_exception",
+    "reason": "[1]: version conflict",
+    "index": "test",
+    "shard": "0"
+  },
+  "status": 409
+}"#;
+
+#[tokio::test]
+pub async fn case01() {
+    let server = StubServer::start(|req| match req.path.contains("/_doc/1") {
+        true => StubResponse::json(409, VERSION_CONFLICT),
+        false => StubResponse::json(400, MAPPING_ERROR),
+    });
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    let err = api
+        .index()
+        .doc(
+            "test",
+            "2",
+            json!({"created_at": "2020-01-01 00:00:00"}),
+            false,
+        )
+        .await
+        .unwrap_err();
+    assert!(err.is_mapping_error(), "{}", err);
+    assert!(!err.is_retryable());
+    let api_error = err.api_error().unwrap();
+    assert_eq!(api_error.status, 400);
+    assert_eq!(api_error.root_cause.len(), 1);
+    assert_eq!(
+        api_error.caused_by.as_ref().unwrap().error_type,
+        "illegal_argument_exception"
+    );
+
+    let err = api
+        .index()
+        .doc("test", "1", json!({}), false)
+        .await
+        .unwrap_err();
+    assert!(err.is_version_conflict(), "{}", err);
+    assert_eq!(err.status(), Some(409));
+    assert_eq!(err.api_error().unwrap().index.as_deref(), Some("test"));
+    assert_eq!(err.api_error().unwrap().shard.as_deref(), Some("0"));
+}
+
+#[test]
+pub fn case02() {
+    let err = ElasticError::from_status(
+        404,
+        r#"{"error":{"type":"index_not_found_exception","reason":"no such index [a]","index":"a"},"status":404}"#
+            .to_string(),
+    );
+    assert!(err.is_index_not_found());
+    assert!(err.is_not_found());
+    assert_eq!(
+        err.to_string(),
+        "[404] index_not_found_exception: no such index [a]"
+    );
+
+    let err = ElasticError::from_status(503, "<html>unavailable</html>".to_string());
+    assert!(matches!(err, ElasticError::Status(503, _)));
+    assert!(err.is_retryable());
+
+    let err: Box<dyn std::error::Error> = Box::new(ElasticError::from_status(
+        429,
+        r#"{"error":{"type":"es_rejected_execution_exception","reason":"rejected"},"status":429}"#
+            .to_string(),
+    ));
+    assert!(err.source().is_some());
+}
@@ -0,0 +1,198 @@
+mod common;
+
+use common::{StubResponse, StubServer};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use uiuifree_elastic::{ClientBuilder, GetOptions, MultiGetDoc};
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct User {
+    name: String,
+}
+
+#[tokio::test]
+pub async fn case01() {
+    let server =
+        StubServer::start(
+            |req| match (req.method.as_str(), req.path.split('?').next().unwrap()) {
+                ("POST", "/_mget") => StubResponse::json(
+                    200,
+                    r#"{"docs":[
+                    {"_index":"users","_id":"3","found":true,"_source":{"name":"carol"}},
+                    {"_index":"users","_id":"9","found":false},
+                    {"_index":"archive","_id":"1","found":true,"_source":{"name":"alice"}}
+                ]}"#,
+                ),
+                _ => StubResponse::json(404, "{}"),
+            },
+        );
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    let users = api
+        .get()
+        .source_includes(&["name"])
+        .many_docs::<User>(
+            "users",
+            &[
+                MultiGetDoc::new("3"),
+                MultiGetDoc::new("9").routing("b"),
+                MultiGetDoc::new("1").index("archive"),
+            ],
+        )
+        .await
+        .unwrap();
+    let names: Vec<_> = users
+        .iter()
+        .map(|v| v.as_ref().map(|v| v.name.as_str()))
+        .collect();
+    assert_eq!(names, [Some("carol"), None, Some("alice")]);
+
+    let body: Value = serde_json::from_str(&server.requests()[0].body).unwrap();
+    assert_eq!(
+        body,
+        json!({"docs": [
+            {"_index": "users", "_id": "3", "_source": {"includes": ["name"]}},
+            {"_index": "users", "_id": "9", "routing": "b", "_source": {"includes": ["name"]}},
+            {"_index": "archive", "_id": "1", "_source": {"includes": ["name"]}},
+        ]})
+    );
+
+    assert!(api
+        .get()
+        .many::<User>("users", &[])
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+pub async fn case02() {
+    let server = StubServer::start(|_| {
+        StubResponse::json(
+            200,
+            r#"{"docs":[{"_index":"gone","_id":"1","error":{"root_cause":[],"type":"index_not_found_exception","reason":"no such index [gone]"}}]}"#,
+        )
+    });
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    let err = api.get().many::<User>("gone", &["1"]).await.unwrap_err();
+    assert!(err.is_index_not_found());
+    assert_eq!(err.status(), None);
+    assert!(!err.is_retryable());
+}
+
+#[tokio::test]
+pub async fn case03() {
+    let server =
+        StubServer::start(
+            |req| match (req.method.as_str(), req.path.split('?').next().unwrap()) {
+                ("HEAD", "/users/_doc/1") => StubResponse::json(200, ""),
+                ("HEAD", "/users/_doc/2") => StubResponse::json(404, ""),
+                ("HEAD", "/users") => StubResponse::json(200, ""),
+                ("HEAD", _) => StubResponse::json(404, ""),
+                _ => StubResponse::json(500, "{}"),
+            },
+        );
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    assert!(api.get().routing("a").exists("users", "1").await.unwrap());
+    assert_eq!(server.requests()[0].path, "/users/_doc/1?routing=a");
+    assert!(!api.get().exists("users", "2").await.unwrap());
+
+    assert!(api.indices().exists("users").await.unwrap());
+    assert!(!api.indices().exists("missing").await.unwrap());
+
+    let api = ClientBuilder::new()
+        .node("http://127.0.0.1:1")
+        .build()
+        .unwrap();
+    assert!(api.get().exists("users", "1").await.is_err());
+    assert!(api.indices().exists("users").await.is_err());
+}
+
+#[tokio::test]
+pub async fn case04() {
+    let server = StubServer::start(|req| {
+        match (req.method.as_str(), req.path.split('?').next().unwrap()) {
+            ("GET", "/users/_doc/1") => StubResponse::json(
+                200,
+                r#"{"_index":"users","_id":"1","_version":3,"_seq_no":7,"_primary_term":1,"_routing":"a",
+                    "found":true,"_source":{"name":"alice"},"fields":{"tags":["x"]}}"#,
+            ),
+            ("GET", "/users/_source/1") => StubResponse::json(200, r#"{"name":"alice"}"#),
+            _ => StubResponse::json(404, "{}"),
+        }
+    });
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    let options = GetOptions {
+        source_includes: vec!["name".to_string()],
+        source_excludes: vec!["secret".to_string()],
+        stored_fields: vec!["tags".to_string()],
+        routing: Some("a".to_string()),
+        preference: Some("_local".to_string()),
+        realtime: Some(false),
+        refresh: Some(true),
+        version: Some(3),
+    };
+
+    let doc = api
+        .get()
+        .with_options(options.clone())
+        .doc_response::<User>("users", "1")
+        .await
+        .unwrap();
+    assert!(doc.found);
+    assert_eq!(doc._version, Some(3));
+    assert_eq!(doc._seq_no, Some(7));
+    assert_eq!(doc._routing.as_deref(), Some("a"));
+    assert_eq!(doc._source.unwrap().name, "alice");
+    assert_eq!(doc.fields.unwrap()["tags"], json!(["x"]));
+    let path = &server.requests()[0].path;
+    for param in [
+        "routing=a",
+        "_source_includes=name",
+        "_source_excludes=secret",
+        "stored_fields=tags",
+        "preference=_local",
+        "realtime=false",
+        "refresh=true",
+        "version=3",
+    ] {
+        assert!(path.contains(param), "{} in {}", param, path);
+    }
+
+    let user = api
+        .get()
+        .with_options(options.clone())
+        .source::<User>("users", "1")
+        .await
+        .unwrap();
+    assert_eq!(user.name, "alice");
+    let path = &server.requests()[1].path;
+    assert!(path.contains("_source_includes=name"));
+    assert!(!path.contains("stored_fields"));
+
+    api.get()
+        .with_options(options)
+        .exists("users", "1")
+        .await
+        .unwrap();
+    let request = &server.requests()[2];
+    assert_eq!(request.method, "HEAD");
+    for param in [
+        "routing=a",
+        "preference=_local",
+        "realtime=false",
+        "refresh=true",
+        "version=3",
+    ] {
+        assert!(
+            request.path.contains(param),
+            "{} in {}",
+            param,
+            request.path
+        );
+    }
+    assert!(!request.path.contains("_source"));
+}
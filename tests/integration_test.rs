@@ -1,3 +1,4 @@
+#![allow(unused_imports, clippy::to_string_in_format_args)]
 use elastic_parser::aggregation::AggregationResponseParser;
 use elastic_query_builder::aggregation::Aggregation;
 use elastic_query_builder::query::match_query::MatchQuery;
@@ -41,7 +42,8 @@ pub async fn case03() {
         .index()
         .doc(test_index, "25", &test1.clone(), true)
         .await;
-    assert!(res.is_err(), "{}", res.err().unwrap().to_string());
+    let err = res.unwrap_err();
+    assert!(err.is_mapping_error(), "{}", err);
 }
 
 #[tokio::test]
@@ -59,18 +61,18 @@ pub async fn case01() {
 
     // INDEX API テストケース
     assert!(
-        ElasticApi::new(el_client().unwrap())
+        !ElasticApi::new(el_client().unwrap())
             .indices()
             .exists("hoge")
             .await
-            .is_err(),
+            .unwrap(),
         "found hoge"
     );
     if ElasticApi::new(el_client().unwrap())
         .indices()
         .exists(test_index)
         .await
-        .is_ok()
+        .unwrap()
     {
         assert!(
             ElasticApi::new(el_client().unwrap())
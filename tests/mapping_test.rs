@@ -0,0 +1,204 @@
+mod common;
+
+use common::{StubResponse, StubServer};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uiuifree_elastic::{ClientBuilder, ElasticMapping};
+
+#[derive(Serialize, Deserialize, ElasticMapping)]
+#[elastic(dynamic = "strict")]
+struct Article {
+    #[elastic(
+        text,
+        analyzer = "kuromoji",
+        fields(raw = "keyword", kana(type = "text", analyzer = "kana"))
+    )]
+    title: String,
+    tags: Vec<String>,
+    #[elastic(date, format = "yyyy-MM-dd HH:mm:ss")]
+    created_at: Option<String>,
+    #[elastic(keyword, index = false, ignore_above = 256)]
+    thumbnail: String,
+    #[serde(rename = "viewCount")]
+    view_count: u32,
+    score: f64,
+    published: bool,
+    #[elastic(nested)]
+    comments: Vec<Comment>,
+    author: Author,
+    #[serde(skip)]
+    #[allow(dead_code)]
+    cache: Vec<u8>,
+    #[elastic(type = "geo_point")]
+    location: (f64, f64),
+}
+
+#[derive(Serialize, Deserialize, ElasticMapping)]
+struct Comment {
+    body: String,
+}
+
+#[derive(Serialize, Deserialize, ElasticMapping)]
+struct Author {
+    #[elastic(rename = "display_name")]
+    name: String,
+}
+
+#[test]
+pub fn case01() {
+    assert_eq!(
+        Article::mapping(),
+        json!({
+            "dynamic": "strict",
+            "properties": {
+                "title": {
+                    "type": "text",
+                    "analyzer": "kuromoji",
+                    "fields": {
+                        "raw": {"type": "keyword"},
+                        "kana": {"type": "text", "analyzer": "kana"}
+                    }
+                },
+                "tags": {"type": "keyword"},
+                "created_at": {"type": "date", "format": "yyyy-MM-dd HH:mm:ss"},
+                "thumbnail": {"type": "keyword", "index": false, "ignore_above": 256},
+                "viewCount": {"type": "long"},
+                "score": {"type": "double"},
+                "published": {"type": "boolean"},
+                "comments": {
+                    "type": "nested",
+                    "properties": {"body": {"type": "keyword"}}
+                },
+                "author": {
+                    "type": "object",
+                    "properties": {"display_name": {"type": "keyword"}}
+                },
+                "location": {"type": "geo_point"}
+            }
+        })
+    );
+}
+
+#[tokio::test]
+pub async fn case02() {
+    let server = StubServer::start(|req| match req.method.as_str() {
+        "HEAD" => StubResponse::json(200, ""),
+        _ => StubResponse::json(200, r#"{"acknowledged":true}"#),
+    });
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    assert!(api
+        .indices()
+        .recreate_from::<Comment>("comments")
+        .await
+        .unwrap());
+
+    let requests = server.requests();
+    let methods: Vec<&str> = requests.iter().map(|v| v.method.as_str()).collect();
+    assert_eq!(methods, vec!["HEAD", "DELETE", "PUT"]);
+    let body: Value = serde_json::from_str(&requests[2].body).unwrap();
+    assert_eq!(body, Comment::index_body());
+}
+
+#[derive(Serialize, Deserialize, ElasticMapping)]
+#[elastic(dynamic = "strict")]
+struct Product {
+    #[elastic(text, fields(raw = "keyword"))]
+    name: String,
+    price: i64,
+    sku: String,
+    author: Author,
+}
+
+#[tokio::test]
+pub async fn case03() {
+    let server = StubServer::start(|_| {
+        StubResponse::json(
+            200,
+            r#"{"products-v2": {"mappings": {
+                "properties": {
+                    "name": {"type": "text", "fields": {"raw": {"type": "integer"}}},
+                    "price": {"type": "float"},
+                    "stock": {"type": "integer"},
+                    "color": {"type": "text", "fields": {"keyword": {"type": "keyword", "ignore_above": 256}}},
+                    "author": {"properties": {"display_name": {"type": "keyword"}, "email": {"type": "keyword"}}}
+                }
+            }}}"#,
+        )
+    });
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    let diff = api
+        .indices()
+        .validate_mapping_for::<Product>("products")
+        .await
+        .unwrap();
+    assert!(!diff.is_empty());
+    assert_eq!(diff.missing_fields, vec!["author.email", "stock"]);
+    assert_eq!(diff.unmapped_fields, vec!["sku"]);
+    assert_eq!(diff.dynamic_fields, vec!["color"]);
+    let mismatches: Vec<(&str, &str, &str)> = diff
+        .type_mismatches
+        .iter()
+        .map(|v| (v.path.as_str(), v.expected.as_str(), v.actual.as_str()))
+        .collect();
+    assert_eq!(
+        mismatches,
+        vec![
+            ("name.raw", "keyword", "integer"),
+            ("price", "long", "float")
+        ]
+    );
+    assert_eq!(diff.dynamic_setting.as_ref().unwrap().actual, "true");
+    assert!(server.requests()[0].path.starts_with("/products/_mapping"));
+
+    let diff = uiuifree_elastic::MappingDiff::compare(&Product::index_body(), &Product::mapping());
+    assert!(diff.is_empty(), "{}", diff);
+}
+
+#[derive(Serialize, Deserialize, ElasticMapping)]
+#[serde(rename_all = "camelCase")]
+struct Listing {
+    item_name: String,
+    #[serde(rename = "SKU")]
+    stock_code: String,
+    #[serde(flatten)]
+    audit: Audit,
+    #[serde(flatten)]
+    extra: std::collections::HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, ElasticMapping)]
+#[serde(rename_all(serialize = "kebab-case"))]
+struct Audit {
+    created_by: String,
+    updated_at: Option<i64>,
+}
+
+#[test]
+pub fn case04() {
+    // serde rename_all and flatten shape the mapping like the document
+    assert_eq!(
+        Listing::properties(),
+        json!({
+            "itemName": {"type": "keyword"},
+            "SKU": {"type": "keyword"},
+            "created-by": {"type": "keyword"},
+            "updated-at": {"type": "long"}
+        })
+    );
+    let listing = Listing {
+        item_name: "a".to_string(),
+        stock_code: "b".to_string(),
+        audit: Audit {
+            created_by: "c".to_string(),
+            updated_at: Some(1),
+        },
+        extra: Default::default(),
+    };
+    let document = serde_json::to_value(&listing).unwrap();
+    let mut fields: Vec<&String> = document.as_object().unwrap().keys().collect();
+    let properties = Listing::properties();
+    let mut mapped: Vec<&String> = properties.as_object().unwrap().keys().collect();
+    fields.sort();
+    mapped.sort();
+    assert_eq!(fields, mapped);
+}
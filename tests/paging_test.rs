@@ -0,0 +1,359 @@
+mod common;
+
+use common::{StubResponse, StubServer};
+use elastic_query_builder::QueryBuilder;
+use futures_util::TryStreamExt;
+use serde_json::{json, Value};
+use std::time::Duration;
+use uiuifree_elastic::ClientBuilder;
+
+fn paging_server() -> StubServer {
+    StubServer::start(|req| {
+        let path = req.path.split('?').next().unwrap();
+        match (req.method.as_str(), path) {
+            ("POST", "/logs/_pit") => StubResponse::json(200, r#"{"id":"p1"}"#),
+            ("DELETE", "/_pit") => StubResponse::json(200, r#"{"succeeded":true,"num_freed":1}"#),
+            ("POST", "/_search") => {
+                let body: Value = serde_json::from_str(&req.body).unwrap();
+                let page = match body["search_after"][0].as_i64() {
+                    None => {
+                        r#"[{"_index":"logs","_id":"1","_source":{"n":1},"sort":[1,10]},
+                                {"_index":"logs","_id":"2","_source":{"n":2},"sort":[2,11]}]"#
+                    }
+                    Some(2) => {
+                        r#"[{"_index":"logs","_id":"3","_source":{"n":3},"sort":[3,12]},
+                                   {"_index":"logs","_id":"4","_source":{"n":4},"sort":[4,13]}]"#
+                    }
+                    Some(_) => r#"[{"_index":"logs","_id":"5","_source":{"n":5},"sort":[5,14]}]"#,
+                };
+                StubResponse::json(
+                    200,
+                    &format!(r#"{{"pit_id":"p2","took":1,"hits":{{"hits":{}}}}}"#, page),
+                )
+            }
+            _ => StubResponse::json(404, "{}"),
+        }
+    })
+}
+
+fn query() -> QueryBuilder {
+    let mut query = QueryBuilder::new();
+    query.set_size(2);
+    query.set_sort(json!([{"n": "asc"}]));
+    query
+}
+
+#[tokio::test]
+pub async fn case01() {
+    let server = paging_server();
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    let hits: Vec<_> = api
+        .search()
+        .search_after::<Value>("logs", &query(), "1m")
+        .try_collect()
+        .await
+        .unwrap();
+    let ids: Vec<_> = hits.iter().map(|v| v._id.clone().unwrap()).collect();
+    assert_eq!(ids, ["1", "2", "3", "4", "5"]);
+
+    let requests = server.requests();
+    let searches: Vec<Value> = requests
+        .iter()
+        .filter(|v| v.path.starts_with("/_search"))
+        .map(|v| serde_json::from_str(&v.body).unwrap())
+        .collect();
+    assert_eq!(searches.len(), 3);
+    assert_eq!(
+        searches[0]["sort"],
+        json!([{"n": "asc"}, {"_shard_doc": "asc"}])
+    );
+    assert_eq!(searches[0]["pit"], json!({"id": "p1", "keep_alive": "1m"}));
+    assert!(searches[0].get("search_after").is_none());
+    assert_eq!(searches[1]["search_after"], json!([2, 11]));
+    assert_eq!(searches[1]["pit"]["id"], "p2");
+    assert_eq!(searches[2]["search_after"], json!([4, 13]));
+
+    let closes: Vec<_> = requests.iter().filter(|v| v.method == "DELETE").collect();
+    assert_eq!(closes.len(), 1);
+    assert_eq!(
+        serde_json::from_str::<Value>(&closes[0].body).unwrap(),
+        json!({"id": "p2"})
+    );
+}
+
+#[tokio::test]
+pub async fn case02() {
+    let server = paging_server();
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    let mut hits = api.search().search_after::<Value>("logs", &query(), "1m");
+    let first = hits.try_next().await.unwrap().unwrap();
+    assert_eq!(first._id.as_deref(), Some("1"));
+    drop(hits);
+
+    for _ in 0..50 {
+        if server.requests().iter().any(|v| v.method == "DELETE") {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("point in time not closed on drop");
+}
+
+fn scroll_server(fail_on_second_page: bool) -> StubServer {
+    StubServer::start(move |req| {
+        let path = req.path.split('?').next().unwrap();
+        match (req.method.as_str(), path) {
+            ("POST", "/logs/_search") => StubResponse::json(
+                200,
+                r#"{"_scroll_id":"s1","took":1,"hits":{"hits":[
+                    {"_index":"logs","_id":"1","_source":{"n":1}},
+                    {"_index":"logs","_id":"2","_source":{"n":2}}]}}"#,
+            ),
+            ("POST", "/_search/scroll") if fail_on_second_page => StubResponse::json(
+                500,
+                r#"{"error":{"type":"search_phase_execution_exception","reason":"all shards failed"},"status":500}"#,
+            ),
+            ("POST", "/_search/scroll") => {
+                let body: Value = serde_json::from_str(&req.body).unwrap();
+                let page = match body["scroll_id"].as_str() {
+                    Some("s1") => r#"[{"_index":"logs","_id":"3","_source":{"n":3}}]"#,
+                    _ => "[]",
+                };
+                StubResponse::json(
+                    200,
+                    &format!(
+                        r#"{{"_scroll_id":"s2","took":1,"hits":{{"hits":{}}}}}"#,
+                        page
+                    ),
+                )
+            }
+            ("DELETE", "/_search/scroll") => {
+                StubResponse::json(200, r#"{"succeeded":true,"num_freed":1}"#)
+            }
+            _ => StubResponse::json(404, "{}"),
+        }
+    })
+}
+
+fn cleared_scroll_ids(server: &StubServer) -> Vec<Value> {
+    server
+        .requests()
+        .iter()
+        .filter(|v| v.method == "DELETE")
+        .map(|v| serde_json::from_str::<Value>(&v.body).unwrap()["scroll_id"].clone())
+        .collect()
+}
+
+#[tokio::test]
+pub async fn case03() {
+    let server = scroll_server(false);
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    let hits: Vec<_> = api
+        .search()
+        .scroll_stream::<Value>(&["logs"], &query(), "1m")
+        .try_collect()
+        .await
+        .unwrap();
+    let ids: Vec<_> = hits.iter().map(|v| v._id.clone().unwrap()).collect();
+    assert_eq!(ids, ["1", "2", "3"]);
+
+    let requests = server.requests();
+    assert!(requests[0].path.contains("scroll=1m"));
+    let scrolls: Vec<Value> = requests
+        .iter()
+        .filter(|v| v.method == "POST" && v.path.starts_with("/_search/scroll"))
+        .map(|v| serde_json::from_str(&v.body).unwrap())
+        .collect();
+    assert_eq!(scrolls[0], json!({"scroll": "1m", "scroll_id": "s1"}));
+    assert_eq!(scrolls[1], json!({"scroll": "1m", "scroll_id": "s2"}));
+    assert_eq!(cleared_scroll_ids(&server), [json!(["s2"])]);
+
+    api.search().clear_scroll(&["a", "b"]).await.unwrap();
+    assert_eq!(cleared_scroll_ids(&server)[1], json!(["a", "b"]));
+}
+
+#[tokio::test]
+pub async fn case04() {
+    let server = scroll_server(true);
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    let mut hits = api
+        .search()
+        .scroll_stream::<Value>(&["logs"], &query(), "1m");
+    assert!(hits.try_next().await.unwrap().is_some());
+    assert!(hits.try_next().await.unwrap().is_some());
+    assert_eq!(hits.try_next().await.unwrap_err().status(), Some(500));
+
+    for _ in 0..50 {
+        if !cleared_scroll_ids(&server).is_empty() {
+            assert_eq!(cleared_scroll_ids(&server), [json!(["s1"])]);
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("scroll not cleared after an error");
+}
+
+async fn wait_for<F: Fn() -> bool>(done: F) {
+    for _ in 0..50 {
+        if done() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("timed out");
+}
+
+#[tokio::test]
+pub async fn case05() {
+    let server = StubServer::start(|req| {
+        let path = req.path.split('?').next().unwrap();
+        match (req.method.as_str(), path) {
+            ("POST", "/logs/_pit") => StubResponse::json(200, r#"{"id":"p1"}"#),
+            ("DELETE", "/_pit") => StubResponse::json(200, r#"{"succeeded":true}"#),
+            ("POST", "/_search") => {
+                let body: Value = serde_json::from_str(&req.body).unwrap();
+                let slice = body["slice"]["id"].as_u64().unwrap();
+                let page = match body.get("search_after") {
+                    None => format!(
+                        r#"[{{"_id":"{0}a","_source":{{}},"sort":[1]}},{{"_id":"{0}b","_source":{{}},"sort":[2]}}]"#,
+                        slice
+                    ),
+                    Some(_) => "[]".to_string(),
+                };
+                StubResponse::json(200, &format!(r#"{{"hits":{{"hits":{}}}}}"#, page))
+            }
+            _ => StubResponse::json(404, "{}"),
+        }
+    });
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    let slices = api
+        .search()
+        .search_after_slices::<Value>("logs", &query(), "1m", 3);
+    assert_eq!(slices.len(), 3);
+    let hits: Vec<_> = uiuifree_elastic::merge_slices(slices, 2)
+        .try_collect()
+        .await
+        .unwrap();
+    let mut ids: Vec<_> = hits.iter().map(|v| v._id.clone().unwrap()).collect();
+    ids.sort();
+    assert_eq!(ids, ["0a", "0b", "1a", "1b", "2a", "2b"]);
+
+    let requests = server.requests();
+    assert_eq!(
+        requests
+            .iter()
+            .filter(|v| v.path.starts_with("/logs/_pit"))
+            .count(),
+        1
+    );
+    let slices: Vec<Value> = requests
+        .iter()
+        .filter(|v| v.path.starts_with("/_search"))
+        .map(|v| serde_json::from_str::<Value>(&v.body).unwrap()["slice"].clone())
+        .collect();
+    assert!(slices.contains(&json!({"id": 2, "max": 3})));
+
+    wait_for(|| server.requests().iter().any(|v| v.method == "DELETE")).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(
+        server
+            .requests()
+            .iter()
+            .filter(|v| v.method == "DELETE")
+            .count(),
+        1
+    );
+}
+
+#[tokio::test]
+pub async fn case06() {
+    let server = StubServer::start(|req| {
+        let path = req.path.split('?').next().unwrap();
+        match (req.method.as_str(), path) {
+            ("POST", "/logs/_search") => {
+                let body: Value = serde_json::from_str(&req.body).unwrap();
+                let slice = body["slice"]["id"].as_u64().unwrap();
+                StubResponse::json(
+                    200,
+                    &format!(
+                        r#"{{"_scroll_id":"s{0}","hits":{{"hits":[{{"_id":"{0}","_source":{{}}}}]}}}}"#,
+                        slice
+                    ),
+                )
+            }
+            ("POST", "/_search/scroll") => {
+                StubResponse::json(200, r#"{"_scroll_id":"done","hits":{"hits":[]}}"#)
+            }
+            ("DELETE", "/_search/scroll") => StubResponse::json(200, r#"{"succeeded":true}"#),
+            _ => StubResponse::json(404, "{}"),
+        }
+    });
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    let slices = api
+        .search()
+        .scroll_slices::<Value>(&["logs"], &query(), "1m", 2);
+    let mut ids = vec![];
+    for slice in slices {
+        let hits: Vec<_> = slice.try_collect().await.unwrap();
+        ids.extend(hits.into_iter().map(|v| v._id.unwrap()));
+    }
+    assert_eq!(ids, ["0", "1"]);
+    assert_eq!(cleared_scroll_ids(&server).len(), 2);
+}
+
+#[tokio::test]
+pub async fn case07() {
+    let server = StubServer::start(|req| {
+        let path = req.path.split('?').next().unwrap();
+        match (req.method.as_str(), path) {
+            ("POST", "/products/_pit") => StubResponse::json(200, r#"{"id":"p1"}"#),
+            ("DELETE", "/_pit") => StubResponse::json(200, r#"{"succeeded":true}"#),
+            ("POST", "/_search") => StubResponse::json(
+                200,
+                r#"{"pit_id":"p1","took":1,"hits":{"hits":[{"_id":"1","_source":{}}]}}"#,
+            ),
+            _ => StubResponse::json(404, "{}"),
+        }
+    });
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    let closed = || {
+        server
+            .requests()
+            .iter()
+            .filter(|v| v.method == "DELETE")
+            .map(|v| serde_json::from_str::<Value>(&v.body).unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    let pit = api.search().open_pit("products", "5m").await.unwrap();
+    assert_eq!(pit.id(), "p1");
+    assert!(server.requests()[0].path.contains("keep_alive=5m"));
+    let res = api
+        .search()
+        .pit(pit.id(), pit.keep_alive())
+        .search::<Value>(&["ignored"], &QueryBuilder::new())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(res.hits.unwrap().hits.unwrap().len(), 1);
+    let search = server.requests().pop().unwrap();
+    assert!(search.path.starts_with("/_search"));
+    let body: Value = serde_json::from_str(&search.body).unwrap();
+    assert_eq!(body["pit"], json!({"id": "p1", "keep_alive": "5m"}));
+
+    let id = pit.into_id();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(closed().is_empty());
+    api.search().close_pit(&id).await.unwrap();
+    assert_eq!(closed(), [json!({"id": "p1"})]);
+
+    let pit = api.search().open_pit("products", "5m").await.unwrap();
+    drop(pit);
+    wait_for(|| closed().len() == 2).await;
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use common::{StubResponse, StubServer};
+use elasticsearch::http::transport::ConnectionPool;
+use std::net::TcpListener;
+use std::time::Duration;
+use uiuifree_elastic::error::ElasticError;
+use uiuifree_elastic::{ClientBuilder, ElasticApi, MultiNodeConnectionPool, Url};
+
+fn closed_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port())
+}
+
+#[tokio::test]
+pub async fn case01() {
+    // round robin
+    let servers: Vec<StubServer> = (0..3)
+        .map(|_| StubServer::start(|_| StubResponse::json(200, "{}")))
+        .collect();
+    let api = ClientBuilder::new()
+        .nodes(servers.iter().map(|v| v.url.clone()))
+        .build()
+        .unwrap();
+    for _ in 0..6 {
+        assert!(api.indices().exists("test").await.is_ok());
+    }
+    for server in &servers {
+        assert_eq!(server.requests().len(), 2);
+    }
+}
+
+#[tokio::test]
+pub async fn case02() {
+    // dead node is skipped until it resurrects
+    let server = StubServer::start(|_| StubResponse::json(200, "{}"));
+    let dead = closed_url();
+    let api = ClientBuilder::new()
+        .nodes([dead.clone(), server.url.clone()])
+        .dead_timeout(Duration::from_millis(300), Duration::from_secs(1))
+        .build()
+        .unwrap();
+    let pool = api.pool().unwrap();
+
+    assert!(api.indices().exists("test").await.is_err());
+    assert_eq!(pool.alive_urls(), vec![Url::parse(&server.url).unwrap()]);
+    for _ in 0..4 {
+        assert!(api.indices().exists("test").await.is_ok());
+    }
+    assert_eq!(server.requests().len(), 4);
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(pool.alive_urls().len(), 2);
+}
+
+#[tokio::test]
+pub async fn case03() {
+    // sniffing
+    let a = StubServer::start(|_| StubResponse::json(200, "{}"));
+    let b = StubServer::start(|_| StubResponse::json(200, "{}"));
+    let port = |url: &str| Url::parse(url).unwrap().port().unwrap();
+    let nodes = format!(
+        r#"{{"nodes":{{"a":{{"http":{{"publish_address":"localhost/127.0.0.1:{}"}}}},"b":{{"http":{{"publish_address":"127.0.0.1:{}"}}}}}}}}"#,
+        port(&a.url),
+        port(&b.url)
+    );
+    let seed = StubServer::start(
+        move |request| match request.path.starts_with("/_nodes/http") {
+            true => StubResponse::json(200, &nodes),
+            false => StubResponse::json(200, "{}"),
+        },
+    );
+    let api = ClientBuilder::new()
+        .node(&seed.url)
+        .sniff_on_start(true)
+        .build()
+        .unwrap();
+    assert!(api.indices().exists("test").await.is_ok());
+    assert!(api.indices().exists("test").await.is_ok());
+
+    let urls = api.pool().unwrap().urls();
+    assert_eq!(urls.len(), 2);
+    assert!(urls.contains(&Url::parse(&format!("{}/", a.url)).unwrap()));
+    assert!(urls.contains(&Url::parse(&format!("http://127.0.0.1:{}/", port(&b.url))).unwrap()));
+    assert_eq!(seed.requests().len(), 1);
+    assert_eq!(a.requests().len() + b.requests().len(), 2);
+}
+
+#[tokio::test]
+pub async fn case04() {
+    // an empty node list is rejected and an empty sniff keeps the nodes
+    assert!(matches!(
+        MultiNodeConnectionPool::new(vec![]),
+        Err(ElasticError::Config(_))
+    ));
+    let url = Url::parse("http://127.0.0.1:9200").unwrap();
+    let pool = MultiNodeConnectionPool::new(vec![url]).unwrap();
+    pool.reseed(vec![]);
+    assert_eq!(pool.urls(), [Url::parse("http://127.0.0.1:9200/").unwrap()]);
+    let _ = pool.next();
+}
+
+#[tokio::test]
+pub async fn case05() {
+    // ElasticApi::new keeps the pool of build_client and skips the dead node
+    let server = StubServer::start(|_| StubResponse::json(200, "{}"));
+    let client = ClientBuilder::new()
+        .nodes([closed_url(), server.url.clone()])
+        .build_client()
+        .unwrap();
+    let api = ElasticApi::new(client);
+
+    assert!(api.indices().exists("test").await.is_err());
+    assert_eq!(
+        api.pool().unwrap().alive_urls(),
+        vec![Url::parse(&server.url).unwrap()]
+    );
+    for _ in 0..4 {
+        assert!(api.indices().exists("test").await.is_ok());
+    }
+    assert_eq!(server.requests().len(), 4);
+}
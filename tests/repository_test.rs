@@ -0,0 +1,111 @@
+mod common;
+
+use common::{StubResponse, StubServer};
+use elastic_query_builder::QueryBuilder;
+use serde::{Deserialize, Serialize};
+use uiuifree_elastic::{ClientBuilder, Document};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct User {
+    id: u64,
+    tenant: String,
+    name: String,
+}
+
+impl Document for User {
+    fn index_name() -> &'static str {
+        "users"
+    }
+    fn id(&self) -> String {
+        self.id.to_string()
+    }
+    fn routing(&self) -> Option<String> {
+        Some(self.tenant.clone())
+    }
+}
+
+const USER: &str = r#"{"id":1,"tenant":"a","name":"alice"}"#;
+
+#[tokio::test]
+pub async fn case01() {
+    let server = StubServer::start(|req| {
+        let path = req.path.split('?').next().unwrap();
+        match (req.method.as_str(), path) {
+            ("GET", "/users/_source/1") => StubResponse::json(200, USER),
+            ("GET", _) => StubResponse::json(
+                404,
+                r#"{"error":{"type":"resource_not_found_exception","reason":"Document not found"},"status":404}"#,
+            ),
+            ("HEAD", "/users/_doc/1") => StubResponse::json(200, ""),
+            ("HEAD", _) => StubResponse::json(404, ""),
+            ("DELETE", "/users/_doc/1") => StubResponse::json(
+                200,
+                r#"{"_index":"users","_id":"1","_version":2,"result":"deleted"}"#,
+            ),
+            ("DELETE", _) => StubResponse::json(
+                404,
+                r#"{"_index":"users","_id":"2","_version":1,"result":"not_found"}"#,
+            ),
+            ("POST", "/users/_doc/1") => StubResponse::json(201, r#"{"result":"created"}"#),
+            ("POST", "/_bulk") => StubResponse::json(200, r#"{"errors":false,"items":[]}"#),
+            ("POST", "/users/_search") => StubResponse::json(
+                200,
+                &format!(
+                    r#"{{"took":1,"hits":{{"hits":[{{"_index":"users","_id":"1","_source":{}}}]}}}}"#,
+                    USER
+                ),
+            ),
+            _ => StubResponse::json(400, "{}"),
+        }
+    });
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    let repository = api.repository::<User>().with_routing("a");
+    let alice: User = serde_json::from_str(USER).unwrap();
+
+    assert_eq!(repository.find("1").await.unwrap(), Some(alice.clone()));
+    assert_eq!(repository.find("2").await.unwrap(), None);
+    assert!(repository.exists("1").await.unwrap());
+    assert!(!repository.exists("2").await.unwrap());
+    assert!(repository.delete("1").await.unwrap());
+    assert!(!repository.delete("2").await.unwrap());
+    assert!(repository.save(&alice).await.is_ok());
+    assert!(repository
+        .save_all(std::slice::from_ref(&alice))
+        .await
+        .is_ok());
+    assert_eq!(
+        repository.search(&QueryBuilder::new()).await.unwrap(),
+        vec![alice]
+    );
+
+    let requests = server.requests();
+    assert!(requests[0].path.contains("routing=a"));
+    let save = requests
+        .iter()
+        .find(|v| v.path.starts_with("/users/_doc/1?"))
+        .unwrap();
+    assert!(save.path.contains("routing=a"));
+    let bulk = requests
+        .iter()
+        .find(|v| v.path.starts_with("/_bulk"))
+        .unwrap();
+    assert!(bulk
+        .body
+        .starts_with(r#"{"index":{"_id":"1","_index":"users","routing":"a"}}"#));
+}
+
+#[tokio::test]
+pub async fn case02() {
+    // nothing to save sends nothing
+    let server = StubServer::start(|_| StubResponse::json(400, "{}"));
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    let res = api.repository::<User>().save_all(&[]).await.unwrap();
+    assert!(res.items.is_empty());
+    assert!(!res.errors);
+
+    assert!(api.bulk().operations(&[], false).await.is_ok());
+    let report = api.bulk().operations_with_retry(&[], false).await.unwrap();
+    assert_eq!(report.requests, 0);
+    assert!(report.is_success());
+    assert!(server.requests().is_empty());
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use common::{StubResponse, StubServer};
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use uiuifree_elastic::{ClientBuilder, ElasticApi, RetryPolicy};
+
+fn flaky(failures: usize) -> StubServer {
+    let count = AtomicUsize::new(0);
+    StubServer::start(
+        move |_| match count.fetch_add(1, Ordering::SeqCst) < failures {
+            true => StubResponse::json(503, "{}"),
+            false => StubResponse::json(201, r#"{"result":"created"}"#),
+        },
+    )
+}
+
+fn policy() -> RetryPolicy {
+    RetryPolicy::new(3).backoff(Duration::from_millis(10), Duration::from_millis(50))
+}
+
+#[tokio::test]
+pub async fn case01() {
+    // idempotent operations retry with the client policy
+    let server = flaky(2);
+    let api = ClientBuilder::new()
+        .node(&server.url)
+        .retry_policy(policy())
+        .build()
+        .unwrap();
+    assert!(api
+        .index()
+        .doc("test", "1", json!({"a": 1}), false)
+        .await
+        .is_ok());
+    assert_eq!(server.requests().len(), 3);
+
+    let server = flaky(3);
+    let api = ClientBuilder::new()
+        .node(&server.url)
+        .retry_policy(policy())
+        .build()
+        .unwrap();
+    assert!(api
+        .index()
+        .doc("test", "1", json!({"a": 1}), false)
+        .await
+        .is_err());
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[tokio::test]
+pub async fn case02() {
+    // auto-id writes only retry with a per-call policy
+    let server = flaky(1);
+    let api = ClientBuilder::new()
+        .node(&server.url)
+        .retry_policy(policy())
+        .build()
+        .unwrap();
+    assert!(api
+        .index()
+        .create("test", json!({"a": 1}), false)
+        .await
+        .is_err());
+    assert_eq!(server.requests().len(), 1);
+
+    assert!(api
+        .index()
+        .with_retry(policy())
+        .create("test", json!({"a": 1}), false)
+        .await
+        .is_ok());
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+pub async fn case03() {
+    // updates and scroll continuations are not repeated by the client policy
+    let server = flaky(1);
+    let api = ClientBuilder::new()
+        .node(&server.url)
+        .retry_policy(policy())
+        .build()
+        .unwrap();
+    let lines = vec![
+        json!({"update": {"_index": "test", "_id": "1"}}),
+        json!({"script": {"source": "ctx._source.n += 1"}}),
+    ];
+    assert!(api.bulk().bulk(lines, false).await.is_err());
+    assert_eq!(server.requests().len(), 1);
+
+    let server = flaky(1);
+    let api = ClientBuilder::new()
+        .node(&server.url)
+        .retry_policy(policy())
+        .build()
+        .unwrap();
+    assert!(api
+        .search()
+        .scroll::<serde_json::Value>("s1", "1m")
+        .await
+        .is_err());
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+pub async fn case04() {
+    // the configured policy survives build_client and ElasticApi::new
+    let server = flaky(2);
+    let client = ClientBuilder::new()
+        .node(&server.url)
+        .retry_policy(policy())
+        .build_client()
+        .unwrap();
+    let api = ElasticApi::new(client);
+    assert!(api
+        .index()
+        .doc("test", "1", json!({"a": 1}), false)
+        .await
+        .is_ok());
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[tokio::test]
+pub async fn case05() {
+    // deletes and conditional bulk writes only retry with a per-call policy
+    let server = flaky(1);
+    let api = ClientBuilder::new()
+        .node(&server.url)
+        .retry_policy(policy())
+        .build()
+        .unwrap();
+    assert!(api.delete().if_seq_no(1, 1).doc("test", "1").await.is_err());
+    assert_eq!(server.requests().len(), 1);
+
+    let server = flaky(1);
+    let api = ClientBuilder::new()
+        .node(&server.url)
+        .retry_policy(policy())
+        .build()
+        .unwrap();
+    let lines = vec![
+        json!({"delete": {"_index": "test", "_id": "1", "if_seq_no": 1, "if_primary_term": 1}}),
+        json!({"index": {"_index": "test", "_id": "2", "version": 3, "version_type": "external"}}),
+        json!({"a": 1}),
+    ];
+    assert!(api.bulk().bulk(lines, false).await.is_err());
+    assert_eq!(server.requests().len(), 1);
+}
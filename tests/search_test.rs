@@ -0,0 +1,106 @@
+mod common;
+
+use common::{StubResponse, StubServer};
+use elastic_query_builder::query::term_query::TermQuery;
+use elastic_query_builder::QueryBuilder;
+use serde_json::{json, Value};
+use uiuifree_elastic::{ClientBuilder, CountOptions};
+
+#[tokio::test]
+pub async fn case01() {
+    let server = StubServer::start(|req| {
+        let path = req.path.split('?').next().unwrap();
+        match (req.method.as_str(), path) {
+            ("POST", "/a,b/_count") | ("POST", "/_count") => StubResponse::json(
+                200,
+                r#"{"count":12345,"_shards":{"total":2,"successful":2,"skipped":0,"failed":0}}"#,
+            ),
+            _ => StubResponse::json(404, "{}"),
+        }
+    });
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    let mut query = QueryBuilder::new();
+    query.set_query(TermQuery::new("tag", "x"));
+    query.set_size(10);
+    let res = api.search().count(&["a", "b"], &query).await.unwrap();
+    assert_eq!(res.count, 12345);
+    assert_eq!(res._shards.unwrap().successful, Some(2));
+    let request = server.requests().pop().unwrap();
+    let body: Value = serde_json::from_str(&request.body).unwrap();
+    assert_eq!(body, json!({"query": {"term": {"tag": {"value": "x"}}}}));
+    assert!(!request.path.contains('?'));
+
+    let options = CountOptions {
+        ignore_unavailable: Some(true),
+        allow_no_indices: Some(false),
+    };
+    api.search()
+        .count_with(&[], &QueryBuilder::new(), &options)
+        .await
+        .unwrap();
+    let request = server.requests().pop().unwrap();
+    assert!(request.path.starts_with("/_count?"));
+    assert!(request.path.contains("ignore_unavailable=true"));
+    assert!(request.path.contains("allow_no_indices=false"));
+}
+
+#[derive(Debug, serde::Deserialize, Clone)]
+struct Product {
+    name: String,
+}
+
+#[tokio::test]
+pub async fn case02() {
+    let server = StubServer::start(|req| {
+        match (req.method.as_str(), req.path.split('?').next().unwrap()) {
+            ("POST", "/_msearch") => StubResponse::json(
+                200,
+                r#"{"took":3,"responses":[
+                    {"took":1,"hits":{"total":{"value":1},"hits":[{"_id":"1","_source":{"name":"pen"}}]},"status":200},
+                    {"error":{"type":"index_not_found_exception","reason":"no such index [missing]","index":"missing"},"status":404},
+                    {"took":1,"hits":{"hits":[]},"aggregations":{"tags":{"buckets":[]}},"status":200}
+                ]}"#,
+            ),
+            _ => StubResponse::json(404, "{}"),
+        }
+    });
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    let mut products = QueryBuilder::new();
+    products.set_size(5);
+    let facets = QueryBuilder::new();
+    let res = api
+        .search()
+        .msearch(&[
+            (&["products"], &products),
+            (&["missing"], &facets),
+            (&[], &facets),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(res.len(), 3);
+    let first = res.get::<Product>(0).unwrap();
+    assert_eq!(
+        first.hits.unwrap().hits.unwrap()[0]
+            ._source
+            .as_ref()
+            .unwrap()
+            .name,
+        "pen"
+    );
+    assert!(res.get::<Product>(1).unwrap_err().is_index_not_found());
+    assert!(res.get::<Value>(2).unwrap().aggregations.is_some());
+    assert!(res.get::<Value>(3).is_err());
+
+    let request = server.requests().pop().unwrap();
+    let lines: Vec<Value> = request
+        .body
+        .lines()
+        .map(|v| serde_json::from_str(v).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[0], json!({"index": ["products"]}));
+    assert_eq!(lines[1]["size"], 5);
+    assert_eq!(lines[4], json!({}));
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::{StubResponse, StubServer};
+use elastic_query_builder::QueryBuilder;
+use serde_json::{json, Value};
+use uiuifree_elastic::error::ElasticError;
+use uiuifree_elastic::ClientBuilder;
+
+const INDEX_EXISTS: &str = r#"{
+  "error": {
+    "root_cause": [{"type": "resource_already_exists_exception", "reason": "index [test/abc] already exists", "index": "test"}],
+    "type": "resource_already_exists_exception",
+    "reason": "index [test/abc] already exists",
+    "index": "test"
+  },
+  "status": 400
+}"#;
+
+#[tokio::test]
+pub async fn case01() {
+    // errors are never reported as Ok
+    let server = StubServer::start(|req| match req.path.as_str() {
+        "/test" if req.method == "PUT" => StubResponse::json(400, INDEX_EXISTS),
+        "/missing" => StubResponse::json(404, ""),
+        _ => StubResponse::json(500, "internal"),
+    });
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    let err = api.indices().create("test", json!({})).await.unwrap_err();
+    assert_eq!(
+        err.api_error().unwrap().error_type,
+        "resource_already_exists_exception"
+    );
+    assert!(matches!(
+        api.indices().delete("test").await,
+        Err(ElasticError::Status(500, _))
+    ));
+    assert!(matches!(
+        api.indices().get_alias(&["test"]).await,
+        Err(ElasticError::Status(500, _))
+    ));
+    assert!(matches!(api.indices().exists("missing").await, Ok(false)));
+    assert!(matches!(
+        api.indices().exists("test").await,
+        Err(ElasticError::Status(500, _))
+    ));
+
+    let mut query = QueryBuilder::new();
+    query.set_scroll("1m");
+    assert!(api
+        .search()
+        .search::<Value>(&["test"], &query)
+        .await
+        .is_err());
+    assert!(api.search().scroll::<Value>("id", "1m").await.is_err());
+    assert!(api
+        .search()
+        .first_search::<Value>("test", QueryBuilder::new())
+        .await
+        .is_err());
+    assert!(api
+        .bulk()
+        .insert_index("test", vec![json!({"a": 1})])
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+pub async fn case02() {
+    // transport failures are connection errors
+    let api = ClientBuilder::new()
+        .node("http://127.0.0.1:1")
+        .build()
+        .unwrap();
+    let err = api.indices().exists("test").await.unwrap_err();
+    assert!(matches!(err, ElasticError::Connection(_)), "{:?}", err);
+    assert!(err.is_retryable());
+}
@@ -0,0 +1,253 @@
+#![cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+
+mod common;
+
+use common::{serve, StubResponse, StubServer};
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslAcceptor, SslMethod, SslVerifyMode};
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+use openssl::x509::{X509NameBuilder, X509};
+use std::path::PathBuf;
+use std::sync::Arc;
+use uiuifree_elastic::error::ElasticError;
+use uiuifree_elastic::ClientBuilder;
+
+struct Certs {
+    dir: PathBuf,
+    ca: X509,
+    server: (X509, PKey<Private>),
+}
+
+fn issue(cn: &str, issuer: Option<(&X509, &PKey<Private>)>, san: bool) -> (X509, PKey<Private>) {
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", cn).unwrap();
+    let name = name.build();
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    let mut serial = BigNum::new().unwrap();
+    serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+    builder
+        .set_serial_number(&serial.to_asn1_integer().unwrap())
+        .unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    match issuer {
+        Some((cert, issuer_key)) => {
+            builder.set_issuer_name(cert.subject_name()).unwrap();
+            if san {
+                let ext = SubjectAlternativeName::new()
+                    .dns("localhost")
+                    .ip("127.0.0.1")
+                    .build(&builder.x509v3_context(Some(cert), None))
+                    .unwrap();
+                builder.append_extension(ext).unwrap();
+            }
+            builder.sign(issuer_key, MessageDigest::sha256()).unwrap();
+        }
+        None => {
+            builder.set_issuer_name(&name).unwrap();
+            builder
+                .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                .unwrap();
+            builder.sign(&key, MessageDigest::sha256()).unwrap();
+        }
+    }
+    (builder.build(), key)
+}
+
+fn certs(name: &str) -> Certs {
+    let dir = std::env::temp_dir().join(format!(
+        "uiuifree-elastic-tls-{}-{}",
+        name,
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (ca, ca_key) = issue("test ca", None, false);
+    let server = issue("localhost", Some((&ca, &ca_key)), true);
+    let (client, client_key) = issue("client", Some((&ca, &ca_key)), false);
+    std::fs::write(dir.join("ca.crt"), ca.to_pem().unwrap()).unwrap();
+    std::fs::write(dir.join("client.crt"), client.to_pem().unwrap()).unwrap();
+    std::fs::write(
+        dir.join("client.key"),
+        client_key.private_key_to_pem_pkcs8().unwrap(),
+    )
+    .unwrap();
+    Certs { dir, ca, server }
+}
+
+fn fingerprint(cert: &X509) -> String {
+    cert.digest(MessageDigest::sha256())
+        .unwrap()
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn tls_server(certs: &Certs, require_client_cert: bool) -> StubServer {
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    acceptor.set_certificate(&certs.server.0).unwrap();
+    acceptor.set_private_key(&certs.server.1).unwrap();
+    acceptor.add_extra_chain_cert(certs.ca.clone()).unwrap();
+    if require_client_cert {
+        acceptor
+            .cert_store_mut()
+            .add_cert(certs.ca.clone())
+            .unwrap();
+        acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+    let acceptor = Arc::new(acceptor.build());
+    StubServer::listen(
+        "https",
+        |_| StubResponse::json(200, "{}"),
+        move |stream, handler, requests| {
+            if let Ok(stream) = acceptor.accept(stream) {
+                serve(stream, handler, requests)
+            }
+        },
+    )
+}
+
+#[tokio::test]
+pub async fn case01() {
+    // CA bundle
+    let certs = certs("ca");
+    let server = tls_server(&certs, false);
+
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    assert!(api.indices().exists("test").await.is_err(), "untrusted CA");
+
+    let api = ClientBuilder::new()
+        .node(&server.url)
+        .ca_cert(certs.dir.join("ca.crt"))
+        .build()
+        .unwrap();
+    let res = api.indices().exists("test").await;
+    assert!(res.is_ok(), "{}", res.unwrap_err());
+}
+
+#[tokio::test]
+pub async fn case02() {
+    // fingerprint pinning
+    let certs = certs("pin");
+    let server = tls_server(&certs, false);
+
+    let api = ClientBuilder::new()
+        .node(&server.url)
+        .ca_fingerprint(&fingerprint(&certs.ca))
+        .build()
+        .unwrap();
+    let res = api.indices().exists("test").await;
+    assert!(res.is_ok(), "{}", res.unwrap_err());
+
+    let other = issue("other ca", None, false).0;
+    let res = ClientBuilder::new()
+        .node(&server.url)
+        .ca_fingerprint(&fingerprint(&other))
+        .build();
+    assert!(matches!(res, Err(ElasticError::Config(_))));
+}
+
+#[tokio::test]
+pub async fn case03() {
+    // insecure
+    let certs = certs("insecure");
+    let server = tls_server(&certs, false);
+
+    let api = ClientBuilder::new()
+        .node(&server.url)
+        .insecure(true)
+        .build()
+        .unwrap();
+    let res = api.indices().exists("test").await;
+    assert!(res.is_ok(), "{}", res.unwrap_err());
+}
+
+#[tokio::test]
+pub async fn case04() {
+    // mutual TLS
+    let certs = certs("mtls");
+    let server = tls_server(&certs, true);
+
+    let api = ClientBuilder::new()
+        .node(&server.url)
+        .ca_cert(certs.dir.join("ca.crt"))
+        .build()
+        .unwrap();
+    assert!(
+        api.indices().exists("test").await.is_err(),
+        "no client cert"
+    );
+
+    let api = ClientBuilder::new()
+        .node(&server.url)
+        .ca_cert(certs.dir.join("ca.crt"))
+        .client_cert(certs.dir.join("client.crt"), certs.dir.join("client.key"))
+        .basic_auth("elastic", "changeme")
+        .build()
+        .unwrap();
+    let res = api.indices().exists("test").await;
+    assert!(res.is_ok(), "{}", res.unwrap_err());
+    let request = server.requests().pop().unwrap();
+    assert_eq!(
+        request.header("authorization"),
+        Some("Basic ZWxhc3RpYzpjaGFuZ2VtZQ==")
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+pub async fn case05() {
+    // pinning skips a node that is down, without stalling the runtime
+    let certs = certs("pin down");
+    let server = tls_server(&certs, false);
+
+    let res = ClientBuilder::new()
+        .nodes(["https://127.0.0.1:1", server.url.as_str()])
+        .ca_fingerprint(&fingerprint(&certs.ca))
+        .build();
+    assert!(res.is_ok(), "{:?}", res.err());
+
+    let res = ClientBuilder::new()
+        .node("https://127.0.0.1:1")
+        .ca_fingerprint(&fingerprint(&certs.ca))
+        .build();
+    assert!(matches!(res, Err(ElasticError::Connection(_))));
+}
+
+#[tokio::test]
+pub async fn case06() {
+    // build_async keeps a current-thread runtime running during pinning
+    let certs = certs("pin async");
+    let server = tls_server(&certs, false);
+    let ticks = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let ticker = tokio::spawn({
+        let ticks = ticks.clone();
+        async move {
+            loop {
+                ticks.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                tokio::task::yield_now().await;
+            }
+        }
+    });
+
+    let api = ClientBuilder::new()
+        .node(&server.url)
+        .ca_fingerprint(&fingerprint(&certs.ca))
+        .build_async()
+        .await
+        .unwrap();
+    ticker.abort();
+    assert!(ticks.load(std::sync::atomic::Ordering::SeqCst) > 0);
+    assert!(api.indices().exists("test").await.is_ok());
+}
@@ -0,0 +1,289 @@
+mod common;
+
+use common::{StubResponse, StubServer};
+use flate2::read::GzDecoder;
+use serde_json::{json, Value};
+use std::io::{Cursor, Read};
+use uiuifree_elastic::error::ElasticError;
+use uiuifree_elastic::{ClientBuilder, ExportOptions, ImportOptions};
+
+fn export_server() -> StubServer {
+    StubServer::start(|req| {
+        let path = req.path.split('?').next().unwrap();
+        match (req.method.as_str(), path) {
+            ("POST", "/users/_pit") => StubResponse::json(200, r#"{"id":"p1"}"#),
+            ("DELETE", "/_pit") => StubResponse::json(200, r#"{"succeeded":true,"num_freed":1}"#),
+            ("POST", "/_search") => {
+                let body: Value = serde_json::from_str(&req.body).unwrap();
+                let page = match body["search_after"][0].as_i64() {
+                    None => {
+                        r#"[{"_index":"users","_id":"1","_routing":"a","_source":{"n":1},"sort":[1]},
+                            {"_index":"users","_id":"2","_source":{"n":2},"sort":[2]}]"#
+                    }
+                    Some(_) => r#"[{"_index":"users","_id":"3","_source":{"n":3},"sort":[3]}]"#,
+                };
+                StubResponse::json(200, &format!(r#"{{"hits":{{"hits":{}}}}}"#, page))
+            }
+            ("POST", "/_bulk") => {
+                let items: Vec<Value> = req
+                    .body
+                    .lines()
+                    .step_by(2)
+                    .map(|v| {
+                        let action: Value = serde_json::from_str(v).unwrap();
+                        let id = action["index"]["_id"].clone();
+                        match id.as_str() {
+                            Some("3") => json!({"index": {"_id": id, "status": 400,
+                                "error": {"type": "mapper_parsing_exception", "reason": "bad"}}}),
+                            _ => json!({"index": {"_id": id, "status": 201, "result": "created"}}),
+                        }
+                    })
+                    .collect();
+                StubResponse::json(200, &json!({"errors": true, "items": items}).to_string())
+            }
+            ("GET", "/users") => StubResponse::json(
+                200,
+                r#"{"users_v2":{"aliases":{"users":{}},"mappings":{"properties":{"n":{"type":"long"}}},
+                    "settings":{"index":{"number_of_shards":"2","uuid":"x","creation_date":"1",
+                    "provided_name":"users_v2","version":{"created":"8050099"}}}}}"#,
+            ),
+            ("PUT", "/copy") => StubResponse::json(200, r#"{"acknowledged":true}"#),
+            _ => StubResponse::json(404, "{}"),
+        }
+    })
+}
+
+#[tokio::test]
+pub async fn case01() {
+    // export with routing and source filtering, gzipped
+    let server = export_server();
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+
+    let mut options = ExportOptions {
+        page_size: 2,
+        ..Default::default()
+    };
+    let mut plain = vec![];
+    let count = api
+        .indices()
+        .export_index_with("users", &mut plain, &options)
+        .await
+        .unwrap();
+    assert_eq!(count, 3);
+    let lines: Vec<Value> = String::from_utf8(plain.clone())
+        .unwrap()
+        .lines()
+        .map(|v| serde_json::from_str(v).unwrap())
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            json!({"_id": "1", "_routing": "a", "_source": {"n": 1}}),
+            json!({"_id": "2", "_source": {"n": 2}}),
+            json!({"_id": "3", "_source": {"n": 3}}),
+        ]
+    );
+
+    options.source_excludes = vec!["secret".to_string()];
+    options.gzip = true;
+    let mut gzip = vec![];
+    api.indices()
+        .export_index_with("users", &mut gzip, &options)
+        .await
+        .unwrap();
+    let mut decoded = vec![];
+    GzDecoder::new(&gzip[..]).read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, plain);
+    let search = server
+        .requests()
+        .into_iter()
+        .rfind(|v| v.path.starts_with("/_search"))
+        .unwrap();
+    let body: Value = serde_json::from_str(&search.body).unwrap();
+    assert_eq!(
+        body["_source"],
+        json!({"includes": [], "excludes": ["secret"]})
+    );
+    assert_eq!(body["size"], 2);
+}
+
+#[tokio::test]
+pub async fn case02() {
+    // import through bulk, keeping ids and routing
+    let server = export_server();
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    let mut file = vec![];
+    api.indices()
+        .export_index_with(
+            "users",
+            &mut file,
+            &ExportOptions {
+                gzip: true,
+                page_size: 2,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    let options = ImportOptions {
+        gzip: true,
+        max_actions: 2,
+        ..Default::default()
+    };
+    let err = api
+        .indices()
+        .import_index_with("copy", Cursor::new(file.clone()), &options)
+        .await
+        .unwrap_err();
+    let failure = match err {
+        ElasticError::ImportFailure(v) => v,
+        e => panic!("{:?}", e),
+    };
+    assert_eq!(failure.stats.failed, 1);
+    assert_eq!(failure.failures.len(), 1);
+    assert_eq!(failure.failures[0].source, Some(json!({"n": 3})));
+
+    let bulks: Vec<_> = server
+        .requests()
+        .into_iter()
+        .filter(|v| v.path.starts_with("/_bulk"))
+        .collect();
+    assert_eq!(bulks.len(), 2);
+    let first: Vec<Value> = bulks[0]
+        .body
+        .lines()
+        .map(|v| serde_json::from_str(v).unwrap())
+        .collect();
+    assert_eq!(
+        first,
+        [
+            json!({"index": {"_index": "copy", "_id": "1", "routing": "a"}}),
+            json!({"n": 1}),
+            json!({"index": {"_index": "copy", "_id": "2"}}),
+            json!({"n": 2}),
+        ]
+    );
+
+    let stats = api
+        .indices()
+        .import_index("copy", &b"\n"[..])
+        .await
+        .unwrap();
+    assert_eq!(stats.requests, 0);
+    assert!(api
+        .indices()
+        .import_index("copy", &b"{not json}\n"[..])
+        .await
+        .is_err());
+
+    let options = ImportOptions {
+        gzip: true,
+        max_actions: 2,
+        max_failures: 0,
+        ..Default::default()
+    };
+    let err = api
+        .indices()
+        .import_index_with("copy", Cursor::new(file), &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ElasticError::ImportFailure(v)
+        if v.stats.failed == 1 && v.failures.is_empty()));
+}
+
+#[tokio::test]
+pub async fn case03() {
+    // header recreates the index
+    let server = export_server();
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    let header = api.indices().header("users").await.unwrap();
+    assert_eq!(header.settings, json!({"index": {"number_of_shards": "2"}}));
+    assert_eq!(header.aliases, json!({"users": {}}));
+
+    assert!(api
+        .indices()
+        .create_from_header("copy", &header, false)
+        .await
+        .unwrap());
+    let request = server.requests().pop().unwrap();
+    let body: Value = serde_json::from_str(&request.body).unwrap();
+    assert_eq!(
+        body,
+        json!({
+            "mappings": {"properties": {"n": {"type": "long"}}},
+            "settings": {"index": {"number_of_shards": "2"}},
+        })
+    );
+
+    assert!(api
+        .indices()
+        .create_from_header("copy", &header, true)
+        .await
+        .unwrap());
+    let request = server.requests().pop().unwrap();
+    let body: Value = serde_json::from_str(&request.body).unwrap();
+    assert_eq!(body["aliases"], json!({"users": {}}));
+}
+
+#[tokio::test]
+pub async fn case04() {
+    // the header line recreates the index before the documents are imported
+    let server = export_server();
+    let api = ClientBuilder::new().node(&server.url).build().unwrap();
+    let mut file = vec![];
+    api.indices()
+        .export_index_with(
+            "users",
+            &mut file,
+            &ExportOptions {
+                page_size: 2,
+                header: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    let first: Value = serde_json::from_slice(file.split(|v| *v == b'\n').next().unwrap()).unwrap();
+    assert_eq!(first["_header"]["aliases"], json!({"users": {}}));
+
+    let options = ImportOptions {
+        create_index: true,
+        ..Default::default()
+    };
+    let err = api
+        .indices()
+        .import_index_with("copy", Cursor::new(file.clone()), &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ElasticError::ImportFailure(v) if v.failures.len() == 1));
+    let requests: Vec<_> = server
+        .requests()
+        .into_iter()
+        .filter(|v| v.path.starts_with("/copy") || v.path.starts_with("/_bulk"))
+        .collect();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].method, "PUT");
+    let body: Value = serde_json::from_str(&requests[0].body).unwrap();
+    assert!(body.get("aliases").is_none());
+    assert_eq!(
+        body["settings"],
+        json!({"index": {"number_of_shards": "2"}})
+    );
+
+    // without create_index the header line is skipped
+    let err = api
+        .indices()
+        .import_index_with("copy", Cursor::new(file), &ImportOptions::default())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ElasticError::ImportFailure(v) if v.failures.len() == 1));
+    assert_eq!(server.requests().last().unwrap().method, "POST");
+
+    let err = api
+        .indices()
+        .import_index_with("copy", &b"{\"_id\":\"1\",\"_source\":{}}\n"[..], &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ElasticError::Config(_)));
+}